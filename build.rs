@@ -0,0 +1,17 @@
+//! Detect the OpenSSL version openssl-sys builds against, to leave out the
+//! calls which only exist since OpenSSL 3.0
+
+use std::env;
+
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(ossl300)");
+
+    // Hex `OPENSSL_VERSION_NUMBER`, set by openssl-sys for its dependents
+    if let Ok(version) = env::var("DEP_OPENSSL_VERSION_NUMBER") {
+        let version = u64::from_str_radix(&version, 16).expect("Invalid OpenSSL version number");
+        // 3.0.0
+        if version >= 0x3000_0000 {
+            println!("cargo:rustc-cfg=ossl300");
+        }
+    }
+}
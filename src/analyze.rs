@@ -0,0 +1,250 @@
+use anyhow::{bail, Context, Result};
+use clap::{ArgGroup, Args};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rsa_decrypt_timing::{
+    harness,
+    stats::{self, Statistic},
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+/// Statistics whose differences get a bootstrapped confidence interval
+const STATISTICS: &[Statistic] = &[
+    Statistic::Median,
+    Statistic::TrimmedMean(0.05),
+    Statistic::TrimmedMean(0.25),
+    Statistic::TrimmedMean(0.45),
+    Statistic::MidHinge,
+];
+
+/// Analyze timing measurements for side channels
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("source").required(true).args(["input", "runs"])))]
+pub struct AnalyzeArgs {
+    /// Class-labelled measurements in the wide layout
+    #[arg(short = 'i', long)]
+    input: Option<String>,
+
+    /// Output files of measure runs over the same ciphertexts, text or
+    /// binary, each run analyzed as one class
+    #[arg(short = 'r', long, num_args = 2..)]
+    runs: Vec<String>,
+
+    /// Significance level of the tests
+    #[arg(short = 'a', long, default_value_t = 0.05)]
+    alpha: f64,
+
+    /// Number of bootstrap resamples for the confidence intervals
+    #[arg(short = 'b', long, default_value_t = 1000,
+          value_parser = clap::value_parser!(u64).range(1..))]
+    bootstrap: u64,
+
+    /// Seed for the bootstrap, for reproducible intervals
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+/// Samples of every class, paired by position
+struct Data {
+    classes: Vec<String>,
+    columns: Vec<Vec<f64>>,
+}
+
+/// Read measurements in the wide layout, one column per class
+fn read_wide(path: &str) -> Result<Data> {
+    let file = File::open(path).context("Failed to open measurements file")?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = lines
+        .next()
+        .context("Measurements file is empty")?
+        .context("Failed to read measurements file")?;
+    let classes: Vec<String> = header.split(',').map(|c| c.trim().to_owned()).collect();
+    let mut columns = vec![Vec::new(); classes.len()];
+
+    for (i, line) in lines.enumerate() {
+        let line = line.context("Failed to read measurements file")?;
+        let values: Vec<&str> = line.split(',').collect();
+        if values.len() != classes.len() {
+            bail!("Wrong number of columns on line {}", i + 2);
+        }
+
+        for (column, value) in columns.iter_mut().zip(values) {
+            column.push(
+                value
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid value on line {}", i + 2))?,
+            );
+        }
+    }
+
+    Ok(Data { classes, columns })
+}
+
+/// Read the durations of `measure` runs, text or binary, one class per run
+///
+/// Samples are paired by ciphertext index and repetition, the n-th
+/// decryption of a ciphertext in one run with its n-th decryption in the
+/// others, whatever the order of the runs. Samples missing from any run are
+/// left out.
+fn read_runs(paths: &[String]) -> Result<Data> {
+    let mut runs = Vec::new();
+
+    for path in paths {
+        let (metadata, samples) = harness::read_output(path)?;
+
+        // Durations in nanoseconds are scaled to seconds, like the wide layout
+        let scale = match metadata.get("unit") {
+            Some("ns") | None => 1e-9,
+            Some(_) => 1.0,
+        };
+
+        let mut repetitions: HashMap<usize, usize> = HashMap::new();
+        let run: Vec<((usize, usize), f64)> = samples
+            .iter()
+            .map(|sample| {
+                let repetition = repetitions.entry(sample.index).or_default();
+                *repetition += 1;
+                ((sample.index, *repetition), sample.duration as f64 * scale)
+            })
+            .collect();
+        runs.push(run);
+    }
+
+    let others: Vec<HashMap<(usize, usize), f64>> = runs[1..]
+        .iter()
+        .map(|run| run.iter().copied().collect())
+        .collect();
+
+    let mut columns = vec![Vec::new(); runs.len()];
+    for (key, duration) in &runs[0] {
+        let paired: Option<Vec<f64>> = others.iter().map(|run| run.get(key).copied()).collect();
+        if let Some(paired) = paired {
+            columns[0].push(*duration);
+            for (column, duration) in columns[1..].iter_mut().zip(paired) {
+                column.push(duration);
+            }
+        }
+    }
+
+    let unpaired: usize =
+        runs.iter().map(Vec::len).sum::<usize>() - columns.len() * columns[0].len();
+    if unpaired > 0 {
+        println!("unpaired samples: {unpaired}");
+    }
+
+    Ok(Data {
+        classes: paths.to_vec(),
+        columns,
+    })
+}
+
+/// Run all the tests and print the report
+pub fn analyze(args: &AnalyzeArgs) -> Result<()> {
+    if args.alpha <= 0.0 || args.alpha >= 1.0 {
+        bail!("The significance level must be between 0 and 1");
+    }
+
+    let data = match &args.input {
+        Some(input) => read_wide(input)?,
+        None => read_runs(&args.runs)?,
+    };
+
+    let n = data.columns.first().map_or(0, Vec::len);
+    if data.classes.len() < 2 {
+        bail!("At least two classes are needed");
+    }
+    if n < 2 {
+        bail!("At least two samples per class are needed");
+    }
+
+    println!("classes: {}", data.classes.len());
+    println!("samples per class: {n}");
+
+    let pairs: Vec<(usize, usize)> = (0..data.classes.len())
+        .flat_map(|a| (a + 1..data.classes.len()).map(move |b| (a, b)))
+        .collect();
+
+    // Bonferroni correction over all the pairwise tests
+    let threshold = args.alpha / pairs.len() as f64;
+    let mut leak = false;
+
+    let friedman = stats::friedman(&data.columns);
+    leak |= friedman < args.alpha;
+    println!();
+    println!("Friedman test p-value: {friedman:.3e}");
+
+    println!();
+    println!(
+        "Pairwise tests (alpha {:.3e} after Bonferroni correction):",
+        threshold
+    );
+    println!(
+        "{:>5} {:>5} {:>10} {:>10} {:>10}",
+        "class", "class", "sign", "wilcoxon", "t-test"
+    );
+
+    for &(a, b) in &pairs {
+        let (x, y) = (&data.columns[a], &data.columns[b]);
+        let sign = stats::sign_test(x, y);
+        let wilcoxon = stats::wilcoxon(x, y);
+        let t_test = stats::paired_t_test(x, y);
+
+        leak |= sign < threshold || wilcoxon < threshold || t_test < threshold;
+        println!("{a:>5} {b:>5} {sign:>10.3e} {wilcoxon:>10.3e} {t_test:>10.3e}");
+    }
+
+    let mut rng = match args.seed {
+        Some(seed) => ChaCha20Rng::seed_from_u64(seed),
+        None => ChaCha20Rng::from_entropy(),
+    };
+    let confidence = 1.0 - args.alpha;
+
+    println!();
+    println!(
+        "Differences from class 0, {:.0}% confidence intervals:",
+        confidence * 100.0
+    );
+
+    for b in 1..data.classes.len() {
+        let diffs: Vec<f64> = data.columns[0]
+            .iter()
+            .zip(&data.columns[b])
+            .map(|(x, y)| y - x)
+            .collect();
+
+        let intervals = stats::bootstrap(
+            &diffs,
+            STATISTICS,
+            args.bootstrap as usize,
+            confidence,
+            &mut rng,
+        );
+
+        println!("class {b}:");
+        for (statistic, (estimate, low, high)) in STATISTICS.iter().zip(intervals) {
+            let name = statistic.to_string();
+            println!("  {name:<18} {estimate:>11.3e} [{low:.3e}, {high:.3e}]");
+        }
+    }
+
+    println!();
+    println!("Classes:");
+    for (i, class) in data.classes.iter().enumerate() {
+        println!("{i:>5} {class}");
+    }
+
+    println!();
+    if leak {
+        println!("verdict: FAIL, timing differences detected");
+    } else {
+        println!("verdict: PASS, no timing differences detected");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use super::{Backend, Config, Decryption, Digest, PaddingMode};
+use crate::outcome::Outcome;
+use anyhow::{bail, Context, Result};
+use aws_lc_rs::rsa::{
+    OaepAlgorithm, OaepPrivateDecryptingKey, Pkcs1PrivateDecryptingKey, PrivateDecryptingKey,
+    OAEP_SHA1_MGF1SHA1, OAEP_SHA256_MGF1SHA256, OAEP_SHA384_MGF1SHA384, OAEP_SHA512_MGF1SHA512,
+};
+use openssl::pkey::{PKey, Private};
+
+enum Key {
+    Pkcs1(Pkcs1PrivateDecryptingKey),
+    Oaep(OaepPrivateDecryptingKey, &'static OaepAlgorithm),
+}
+
+pub struct AwsLcBackend {
+    key: Key,
+    label: Option<Vec<u8>>,
+}
+
+impl AwsLcBackend {
+    pub fn new(pkey: &PKey<Private>, config: &Config) -> Result<Self> {
+        let der = pkey
+            .private_key_to_pkcs8()
+            .context("Failed to encode private key")?;
+        let key = PrivateDecryptingKey::from_pkcs8(&der)
+            .map_err(|e| anyhow::anyhow!("{e}"))
+            .context("Failed to load key into aws-lc")?;
+
+        let key = match config.padding {
+            PaddingMode::Pkcs1 => Key::Pkcs1(
+                Pkcs1PrivateDecryptingKey::new(key)
+                    .map_err(|e| anyhow::anyhow!("{e}"))
+                    .context("Failed to create PKCS1 decrypting key")?,
+            ),
+            PaddingMode::Oaep => {
+                // aws-lc-rs only offers OAEP with the same digest for MGF1
+                let algorithm = match (config.oaep_md, config.mgf1_md) {
+                    (Digest::Sha1, Digest::Sha1) => &OAEP_SHA1_MGF1SHA1,
+                    (Digest::Sha256, Digest::Sha256) => &OAEP_SHA256_MGF1SHA256,
+                    (Digest::Sha384, Digest::Sha384) => &OAEP_SHA384_MGF1SHA384,
+                    (Digest::Sha512, Digest::Sha512) => &OAEP_SHA512_MGF1SHA512,
+                    _ => bail!("The aws-lc backend needs the same OAEP and MGF1 digests"),
+                };
+
+                Key::Oaep(
+                    OaepPrivateDecryptingKey::new(key)
+                        .map_err(|e| anyhow::anyhow!("{e}"))
+                        .context("Failed to create OAEP decrypting key")?,
+                    algorithm,
+                )
+            }
+            PaddingMode::None => bail!("The aws-lc backend does not support raw RSA"),
+        };
+
+        Ok(AwsLcBackend {
+            key,
+            label: config.oaep_label.clone(),
+        })
+    }
+}
+
+impl Backend for AwsLcBackend {
+    fn decrypt(&mut self, ciphertext: &[u8], plaintext: &mut [u8]) -> Result<Decryption> {
+        // aws-lc-rs reports every failure as the same opaque error
+        let res = match &self.key {
+            Key::Pkcs1(key) => key.decrypt(ciphertext, plaintext),
+            Key::Oaep(key, algorithm) => {
+                key.decrypt(algorithm, ciphertext, plaintext, self.label.as_deref())
+            }
+        };
+
+        let res = res
+            .map(|plaintext| plaintext.len())
+            .map_err(|_| Outcome::Padding);
+        Ok(res.into())
+    }
+
+    fn implicit_rejection(&self) -> Option<bool> {
+        Some(false)
+    }
+}
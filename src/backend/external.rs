@@ -0,0 +1,203 @@
+//! Backend delegating decryptions to a helper program
+//!
+//! The helper is spawned with the decryption parameters in its environment,
+//! including the implicit rejection mode (`on`, `off` or `default`) it must
+//! apply to PKCS#1 v1.5 padding errors, and talks to the harness over its
+//! standard input and output. All integers are big endian.
+//!
+//! 1. The harness sends the private key: `u32` length, then the PKCS#8 DER
+//!    encoded key.
+//! 2. The helper answers with one byte: `1` if it times decryptions itself,
+//!    `0` if the harness has to time the round trip.
+//! 3. For each ciphertext the harness sends a `u32` length followed by the
+//!    ciphertext, and the helper answers with:
+//!    - `u8` status: `0` success, `1` padding error, `2` other error
+//!    - `u32` error code, reported for status `2`
+//!    - `u64` decryption time in nanoseconds, ignored when not self-timed
+//!    - `u32` plaintext length, then the plaintext
+//!
+//! The helper should exit when its standard input is closed.
+
+use super::{Backend, Config, Decryption, ImplicitRejection, PaddingMode};
+use crate::outcome::Outcome;
+use anyhow::{bail, Context, Result};
+use openssl::pkey::{PKey, Private};
+use std::{
+    io::{BufReader, BufWriter, Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+pub struct ExternalBackend {
+    child: Child,
+    stdin: Option<BufWriter<ChildStdin>>,
+    stdout: BufReader<ChildStdout>,
+    self_timed: bool,
+    /// Implicit rejection mode the helper was asked to use
+    implicit_rejection: Option<bool>,
+    /// Response buffer, reused to read the plaintext
+    response: Vec<u8>,
+}
+
+impl ExternalBackend {
+    pub fn new(pkey: &PKey<Private>, config: &Config) -> Result<Self> {
+        let Some((program, args)) = config.helper.split_first() else {
+            bail!("The external backend needs a helper program");
+        };
+
+        let padding = match config.padding {
+            PaddingMode::Pkcs1 => "pkcs1",
+            PaddingMode::Oaep => "oaep",
+            PaddingMode::None => "none",
+        };
+        let (implicit_rejection, mode) = match config.implicit_rejection {
+            ImplicitRejection::On => (Some(true), "on"),
+            ImplicitRejection::Off => (Some(false), "off"),
+            ImplicitRejection::Default => (None, "default"),
+        };
+
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env("RSA_DECRYPT_TIMING_PADDING", padding)
+            .env(
+                "RSA_DECRYPT_TIMING_OAEP_MD",
+                format!("{:?}", config.oaep_md).to_lowercase(),
+            )
+            .env(
+                "RSA_DECRYPT_TIMING_MGF1_MD",
+                format!("{:?}", config.mgf1_md).to_lowercase(),
+            )
+            .env("RSA_DECRYPT_TIMING_IMPLICIT_REJECTION", mode)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        // Keep terminal interrupts away from the helper, the harness stops
+        // the run and closes its input
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        if let Some(label) = &config.oaep_label {
+            command.env("RSA_DECRYPT_TIMING_OAEP_LABEL", hex::encode(label));
+        }
+
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to run helper {program}"))?;
+
+        let stdin = child.stdin.take().context("Failed to open helper input")?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("Failed to open helper output")?;
+
+        let mut backend = ExternalBackend {
+            child,
+            stdin: Some(BufWriter::new(stdin)),
+            stdout: BufReader::new(stdout),
+            self_timed: false,
+            implicit_rejection,
+            response: Vec::new(),
+        };
+
+        let der = pkey
+            .private_key_to_pkcs8()
+            .context("Failed to encode private key")?;
+        backend
+            .send(&der)
+            .context("Failed to send the key to the helper")?;
+
+        let mut hello = [0; 1];
+        backend
+            .stdout
+            .read_exact(&mut hello)
+            .context("Failed to read the helper handshake")?;
+        backend.self_timed = match hello[0] {
+            0 => false,
+            1 => true,
+            b => bail!("Invalid helper handshake {b}"),
+        };
+
+        Ok(backend)
+    }
+
+    /// Send one length-prefixed message to the helper
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        let stdin = self.stdin.as_mut().context("Helper input is closed")?;
+        let len: u32 = data.len().try_into().context("Message too long")?;
+
+        stdin.write_all(&len.to_be_bytes())?;
+        stdin.write_all(data)?;
+        stdin.flush()?;
+
+        Ok(())
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        self.stdout.read_exact(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        self.stdout.read_exact(&mut buf)?;
+        Ok(u64::from_be_bytes(buf))
+    }
+}
+
+impl Backend for ExternalBackend {
+    fn decrypt(&mut self, ciphertext: &[u8], plaintext: &mut [u8]) -> Result<Decryption> {
+        self.send(ciphertext)
+            .context("Failed to send ciphertext to the helper")?;
+
+        let mut status = [0; 1];
+        self.stdout
+            .read_exact(&mut status)
+            .context("Failed to read helper response")?;
+        let code = self.read_u32().context("Failed to read helper response")?;
+        let duration = self.read_u64().context("Failed to read helper response")?;
+        let len = self.read_u32().context("Failed to read helper response")? as usize;
+
+        self.response.resize(len, 0);
+        self.stdout
+            .read_exact(&mut self.response)
+            .context("Failed to read helper response")?;
+
+        let outcome = match status[0] {
+            0 => Outcome::Ok,
+            1 => Outcome::Padding,
+            2 => Outcome::Error(code),
+            s => bail!("Invalid helper status {s}"),
+        };
+
+        if len > plaintext.len() {
+            bail!("Helper returned a {len} bytes plaintext");
+        }
+
+        Ok(Decryption {
+            outcome,
+            len,
+            duration: self.self_timed.then_some(duration),
+        })
+    }
+
+    fn complete(&mut self, decryption: &mut Decryption, plaintext: &mut [u8]) -> Result<()> {
+        plaintext[..decryption.len].copy_from_slice(&self.response);
+        Ok(())
+    }
+
+    fn implicit_rejection(&self) -> Option<bool> {
+        // The helper cannot report its mode, trust it to follow the request
+        self.implicit_rejection
+    }
+
+    fn self_timed(&self) -> bool {
+        self.self_timed
+    }
+}
+
+impl Drop for ExternalBackend {
+    fn drop(&mut self) {
+        // Closing the input tells the helper to exit
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+    }
+}
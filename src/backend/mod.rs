@@ -0,0 +1,156 @@
+#[cfg(feature = "aws-lc")]
+mod aws_lc;
+mod external;
+mod openssl;
+#[cfg(feature = "rustcrypto")]
+mod rustcrypto;
+
+use crate::outcome::Outcome;
+use ::openssl::pkey::{PKey, Private};
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+
+/// RSA encryption padding scheme used for decryption
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaddingMode {
+    /// RSAES-PKCS1-v1_5
+    Pkcs1,
+    /// RSAES-OAEP
+    Oaep,
+    /// Raw RSA, no padding check at all
+    None,
+}
+
+/// Digest used by OAEP and MGF1
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Digest {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+/// Implicit rejection setting for PKCS#1 v1.5 decryption
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImplicitRejection {
+    /// Return a synthetic plaintext when the padding check fails
+    On,
+    /// Return an error when the padding check fails
+    Off,
+    /// Keep the library default
+    Default,
+}
+
+/// Library performing the decryptions
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// OpenSSL, through the openssl crate
+    Openssl,
+    /// The RustCrypto rsa crate
+    Rustcrypto,
+    /// AWS-LC, through the aws-lc-rs crate
+    AwsLc,
+    /// A helper program speaking the framing protocol on stdin/stdout
+    External,
+}
+
+/// Decryption parameters shared by all backends
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub padding: PaddingMode,
+    pub oaep_md: Digest,
+    pub mgf1_md: Digest,
+    pub oaep_label: Option<Vec<u8>>,
+    pub implicit_rejection: ImplicitRejection,
+    /// Helper program and its arguments, for the external backend
+    pub helper: Vec<String>,
+}
+
+/// Result of one decryption call
+#[derive(Clone, Copy, Debug)]
+pub struct Decryption {
+    pub outcome: Outcome,
+    /// Length of the plaintext, zero when the decryption failed
+    pub len: usize,
+    /// Decryption time in nanoseconds, when measured by the backend itself
+    pub duration: Option<u64>,
+}
+
+impl From<Result<usize, Outcome>> for Decryption {
+    fn from(res: Result<usize, Outcome>) -> Self {
+        match res {
+            Ok(len) => Decryption {
+                outcome: Outcome::Ok,
+                len,
+                duration: None,
+            },
+            Err(outcome) => Decryption {
+                outcome,
+                len: 0,
+                duration: None,
+            },
+        }
+    }
+}
+
+/// A key loaded into a library, ready to decrypt ciphertexts
+pub trait Backend {
+    /// Decrypt one ciphertext into `plaintext`
+    ///
+    /// Decryption failures are reported in the returned [`Decryption`], an
+    /// error means the backend itself broke down. This is the timed call, it
+    /// should do nothing but the decryption itself.
+    fn decrypt(&mut self, ciphertext: &[u8], plaintext: &mut [u8]) -> Result<Decryption>;
+
+    /// Finish the last decryption, once the clock has stopped
+    ///
+    /// Work which is not part of the decryption, like classifying a failure
+    /// from an error queue or copying the plaintext out of the library, is
+    /// done here, so that it does not skew the timing of some outcomes.
+    fn complete(&mut self, _decryption: &mut Decryption, _plaintext: &mut [u8]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Implicit rejection mode in effect, `None` when the library in use
+    /// predates implicit rejection
+    fn implicit_rejection(&self) -> Option<bool>;
+
+    /// Whether the backend reports its own decryption times, in nanoseconds
+    fn self_timed(&self) -> bool {
+        false
+    }
+}
+
+/// Load the key into the selected library, configured for decryption
+pub fn new_backend(
+    kind: BackendKind,
+    pkey: &PKey<Private>,
+    config: &Config,
+) -> Result<Box<dyn Backend>> {
+    if config.padding != PaddingMode::Pkcs1
+        && config.implicit_rejection != ImplicitRejection::Default
+    {
+        bail!("Implicit rejection can only be set for PKCS1 padding");
+    }
+
+    let backend: Box<dyn Backend> = match kind {
+        BackendKind::Openssl => Box::new(openssl::OpensslBackend::new(pkey, config)?),
+        #[cfg(feature = "rustcrypto")]
+        BackendKind::Rustcrypto => Box::new(rustcrypto::RustCryptoBackend::new(pkey, config)?),
+        #[cfg(feature = "aws-lc")]
+        BackendKind::AwsLc => Box::new(aws_lc::AwsLcBackend::new(pkey, config)?),
+        BackendKind::External => Box::new(external::ExternalBackend::new(pkey, config)?),
+        #[allow(unreachable_patterns)]
+        _ => bail!("Support for the {kind:?} backend is not compiled in"),
+    };
+
+    match (config.implicit_rejection, backend.implicit_rejection()) {
+        (ImplicitRejection::On, Some(false) | None) => {
+            bail!("Implicit rejection is not supported by the {kind:?} backend")
+        }
+        (ImplicitRejection::Off, Some(true)) => bail!("Failed to disable implicit rejection"),
+        _ => (),
+    }
+
+    Ok(backend)
+}
@@ -0,0 +1,242 @@
+use super::{Backend, Config, Decryption, Digest, ImplicitRejection, PaddingMode};
+use crate::outcome::Outcome;
+use anyhow::{Context, Result};
+use foreign_types::ForeignTypeRef;
+use openssl::{
+    error::ErrorStack,
+    md::{Md, MdRef},
+    pkey::{PKey, Private},
+    pkey_ctx::{PkeyCtx, PkeyCtxRef},
+    rsa::Padding,
+};
+#[cfg(ossl300)]
+use std::ffi::c_uint;
+use std::ffi::{c_char, c_int, CStr};
+
+/// OpenSSL library code for errors raised by the RSA module
+const ERR_LIB_RSA: i32 = 4;
+
+/// Control enabling implicit rejection, known to OpenSSL 3.2+ and to the
+/// 1.1.1 and 3.0 builds of distributions which backported it
+const IMPLICIT_REJECTION_CTRL: &CStr = c"rsa_pkcs1_implicit_rejection";
+
+/// Name of the OpenSSL 3.2+ asymmetric cipher parameter behind
+/// [`IMPLICIT_REJECTION_CTRL`]
+#[cfg(ossl300)]
+const IMPLICIT_REJECTION_PARAM: &CStr = c"implicit-rejection";
+
+extern "C" {
+    // Not bound by openssl-sys, present in every supported OpenSSL
+    fn EVP_PKEY_CTX_ctrl_str(
+        ctx: *mut openssl_sys::EVP_PKEY_CTX,
+        name: *const c_char,
+        value: *const c_char,
+    ) -> c_int;
+}
+
+/// OpenSSL RSA reason codes reported when the padding check fails
+const RSA_PADDING_REASONS: &[i32] = &[
+    102, // RSA_R_BAD_FIXED_HEADER_DECRYPT
+    103, // RSA_R_BAD_PAD_BYTE_COUNT
+    106, // RSA_R_BLOCK_TYPE_IS_NOT_01
+    107, // RSA_R_BLOCK_TYPE_IS_NOT_02
+    113, // RSA_R_NULL_BEFORE_BLOCK_TYPE
+    114, // RSA_R_PADDING_CHECK_FAILED
+    121, // RSA_R_OAEP_DECODING_ERROR
+    159, // RSA_R_PKCS_DECODING_ERROR
+];
+
+fn md(digest: Digest) -> &'static MdRef {
+    match digest {
+        Digest::Sha1 => Md::sha1(),
+        Digest::Sha256 => Md::sha256(),
+        Digest::Sha384 => Md::sha384(),
+        Digest::Sha512 => Md::sha512(),
+    }
+}
+
+/// Classify the error stack returned by a failed decryption
+fn outcome(err: &ErrorStack) -> Outcome {
+    match err.errors().first() {
+        Some(e)
+            if e.library_code() == ERR_LIB_RSA
+                && RSA_PADDING_REASONS.contains(&e.reason_code()) =>
+        {
+            Outcome::Padding
+        }
+        Some(e) => Outcome::Error(e.code() as u32),
+        None => Outcome::Error(0),
+    }
+}
+
+/// Enable or disable implicit rejection on a decryption context
+///
+/// Returns whether the library knows the control.
+fn set_implicit_rejection(ctx: &mut PkeyCtxRef<Private>, enabled: bool) -> Result<bool> {
+    let value = if enabled { c"1" } else { c"0" };
+
+    // SAFETY: the strings are constants and the context is valid
+    let ret = unsafe {
+        EVP_PKEY_CTX_ctrl_str(
+            ctx.as_ptr(),
+            IMPLICIT_REJECTION_CTRL.as_ptr(),
+            value.as_ptr(),
+        )
+    };
+
+    match ret {
+        1.. => Ok(true),
+        // Unknown control, the library predates implicit rejection
+        -2 => {
+            let _ = ErrorStack::get();
+            Ok(false)
+        }
+        _ => Err(ErrorStack::get()).context("failed to set RSA implicit rejection"),
+    }
+}
+
+/// Get the implicit rejection mode in effect for a decryption context
+///
+/// Returns `None` when the provider does not know the parameter, which means
+/// the OpenSSL in use predates implicit rejection, and always before OpenSSL
+/// 3.0, which cannot query it.
+#[cfg(not(ossl300))]
+fn implicit_rejection(_ctx: &PkeyCtxRef<Private>) -> Option<bool> {
+    None
+}
+
+/// Get the implicit rejection mode in effect for a decryption context
+///
+/// Returns `None` when the provider does not know the parameter, which means
+/// the OpenSSL in use predates implicit rejection.
+#[cfg(ossl300)]
+fn implicit_rejection(ctx: &PkeyCtxRef<Private>) -> Option<bool> {
+    // Sentinel which the provider overwrites with 0 or 1
+    let mut value: c_uint = c_uint::MAX;
+
+    // SAFETY: the parameter array only borrows `value` and the constant key
+    // for the duration of the call
+    let ret = unsafe {
+        let mut params = [
+            openssl_sys::OSSL_PARAM_construct_uint(IMPLICIT_REJECTION_PARAM.as_ptr(), &mut value),
+            openssl_sys::OSSL_PARAM_construct_end(),
+        ];
+        openssl_sys::EVP_PKEY_CTX_get_params(ctx.as_ptr(), params.as_mut_ptr())
+    };
+
+    if ret <= 0 || value == c_uint::MAX {
+        // Drop anything a failed lookup left on the error queue
+        let _ = ErrorStack::get();
+        return None;
+    }
+
+    Some(value != 0)
+}
+
+/// Get the decrypter set with the configured padding
+pub fn get_decrypter(pkey: &PKey<Private>, config: &Config) -> Result<PkeyCtx<Private>> {
+    let mut decrypter = PkeyCtx::new(pkey).context("Failed to set decrypter key")?;
+
+    decrypter
+        .decrypt_init()
+        .context("Failed to initialize decrypter")?;
+
+    match config.padding {
+        PaddingMode::Pkcs1 => {
+            decrypter
+                .set_rsa_padding(Padding::PKCS1)
+                .context("failed to set RSA decrypter padding")?;
+        }
+        PaddingMode::Oaep => {
+            decrypter
+                .set_rsa_padding(Padding::PKCS1_OAEP)
+                .context("failed to set RSA decrypter padding")?;
+            decrypter
+                .set_rsa_oaep_md(md(config.oaep_md))
+                .context("failed to set RSA OAEP digest")?;
+            decrypter
+                .set_rsa_mgf1_md(md(config.mgf1_md))
+                .context("failed to set RSA MGF1 digest")?;
+            if let Some(label) = &config.oaep_label {
+                decrypter
+                    .set_rsa_oaep_label(label)
+                    .context("failed to set RSA OAEP label")?;
+            }
+        }
+        PaddingMode::None => {
+            decrypter
+                .set_rsa_padding(Padding::NONE)
+                .context("failed to set RSA decrypter padding")?;
+        }
+    }
+
+    Ok(decrypter)
+}
+
+pub struct OpensslBackend {
+    decrypter: PkeyCtx<Private>,
+    /// Implicit rejection mode accepted by the library, for the versions
+    /// which cannot report the mode in effect
+    requested: Option<bool>,
+}
+
+impl OpensslBackend {
+    pub fn new(pkey: &PKey<Private>, config: &Config) -> Result<Self> {
+        let mut decrypter = get_decrypter(pkey, config)?;
+
+        let requested = match config.implicit_rejection {
+            ImplicitRejection::On => Some(true),
+            ImplicitRejection::Off => Some(false),
+            ImplicitRejection::Default => None,
+        };
+        let requested = match requested {
+            Some(enabled) => set_implicit_rejection(&mut decrypter, enabled)?.then_some(enabled),
+            None => None,
+        };
+
+        Ok(OpensslBackend {
+            decrypter,
+            requested,
+        })
+    }
+}
+
+impl Backend for OpensslBackend {
+    #[inline]
+    fn decrypt(&mut self, ciphertext: &[u8], plaintext: &mut [u8]) -> Result<Decryption> {
+        let mut len = plaintext.len();
+
+        // Called directly rather than through `PkeyCtxRef::decrypt`, which
+        // collects the error queue of failed decryptions while timed.
+        // SAFETY: both buffers outlive the call and `len` is the size of the
+        // output buffer
+        let ret = unsafe {
+            openssl_sys::EVP_PKEY_decrypt(
+                self.decrypter.as_ptr(),
+                plaintext.as_mut_ptr(),
+                &mut len,
+                ciphertext.as_ptr(),
+                ciphertext.len(),
+            )
+        };
+
+        // Failures are classified by `complete`
+        Ok(match ret > 0 {
+            true => Ok(len),
+            false => Err(Outcome::Error(0)),
+        }
+        .into())
+    }
+
+    fn complete(&mut self, decryption: &mut Decryption, _plaintext: &mut [u8]) -> Result<()> {
+        if decryption.outcome != Outcome::Ok {
+            decryption.outcome = outcome(&ErrorStack::get());
+        }
+
+        Ok(())
+    }
+
+    fn implicit_rejection(&self) -> Option<bool> {
+        implicit_rejection(&self.decrypter).or(self.requested)
+    }
+}
@@ -0,0 +1,172 @@
+use super::{Backend, Config, Decryption, Digest, PaddingMode};
+use crate::outcome::Outcome;
+use anyhow::{bail, Context, Result};
+use openssl::pkey::{PKey, Private};
+use rand::rngs::ThreadRng;
+use rsa::{
+    hazmat, oaep, pkcs1v15,
+    pkcs8::DecodePrivateKey,
+    traits::{PublicKeyParts, RandomizedDecryptor},
+    BigUint, RsaPrivateKey,
+};
+use sha2::digest::FixedOutputReset;
+
+/// Map an error of the rsa crate, which reports every padding failure as
+/// the same opaque decryption error
+fn outcome(err: rsa::Error) -> Outcome {
+    match err {
+        rsa::Error::Decryption => Outcome::Padding,
+        _ => Outcome::Error(0),
+    }
+}
+
+/// Decrypting key set up for its padding
+trait Decryptor {
+    fn decrypt(&self, rng: &mut ThreadRng, ciphertext: &[u8]) -> rsa::Result<Vec<u8>>;
+}
+
+impl<K: RandomizedDecryptor> Decryptor for K {
+    fn decrypt(&self, rng: &mut ThreadRng, ciphertext: &[u8]) -> rsa::Result<Vec<u8>> {
+        self.decrypt_with_rng(rng, ciphertext)
+    }
+}
+
+/// OAEP decrypting key with the `D` digest and the configured MGF1 digest
+fn oaep_key<D>(key: RsaPrivateKey, mgf1_md: Digest, label: Option<String>) -> Box<dyn Decryptor>
+where
+    D: sha2::Digest + 'static,
+{
+    match mgf1_md {
+        Digest::Sha1 => oaep_key_with::<D, sha1::Sha1>(key, label),
+        Digest::Sha256 => oaep_key_with::<D, sha2::Sha256>(key, label),
+        Digest::Sha384 => oaep_key_with::<D, sha2::Sha384>(key, label),
+        Digest::Sha512 => oaep_key_with::<D, sha2::Sha512>(key, label),
+    }
+}
+
+/// OAEP decrypting key with the `D` and `MGD` digests
+fn oaep_key_with<D, MGD>(key: RsaPrivateKey, label: Option<String>) -> Box<dyn Decryptor>
+where
+    D: sha2::Digest + 'static,
+    MGD: sha2::Digest + FixedOutputReset + 'static,
+{
+    match label {
+        Some(label) => Box::new(oaep::DecryptingKey::<D, MGD>::new_with_label(key, label)),
+        None => Box::new(oaep::DecryptingKey::<D, MGD>::new(key)),
+    }
+}
+
+enum Key {
+    Pkcs1(pkcs1v15::DecryptingKey),
+    Oaep(Box<dyn Decryptor>),
+    Raw(RsaPrivateKey),
+}
+
+/// Result of the last successful decryption, copied to the plaintext
+/// buffer by `complete`
+enum Decrypted {
+    Plaintext(Vec<u8>),
+    Raw(BigUint),
+}
+
+pub struct RustCryptoBackend {
+    key: Key,
+    /// Modulus size in bytes
+    size: usize,
+    decrypted: Option<Decrypted>,
+    rng: ThreadRng,
+}
+
+impl RustCryptoBackend {
+    pub fn new(pkey: &PKey<Private>, config: &Config) -> Result<Self> {
+        let der = pkey
+            .private_key_to_pkcs8()
+            .context("Failed to encode private key")?;
+        let key = RsaPrivateKey::from_pkcs8_der(&der).context("Failed to load key into rsa")?;
+        let size = key.size();
+
+        // The rsa crate only takes UTF-8 OAEP labels
+        let label = match &config.oaep_label {
+            Some(label) => match String::from_utf8(label.clone()) {
+                Ok(label) => Some(label),
+                Err(_) => bail!("The rsa backend only supports UTF-8 OAEP labels"),
+            },
+            None => None,
+        };
+
+        // Keys are set up once, so that the timed calls do not build the
+        // digests and the padding scheme; the rsa crate still copies the
+        // OAEP label on every call
+        let key = match config.padding {
+            PaddingMode::Pkcs1 => Key::Pkcs1(pkcs1v15::DecryptingKey::new(key)),
+            PaddingMode::Oaep => Key::Oaep(match config.oaep_md {
+                Digest::Sha1 => oaep_key::<sha1::Sha1>(key, config.mgf1_md, label),
+                Digest::Sha256 => oaep_key::<sha2::Sha256>(key, config.mgf1_md, label),
+                Digest::Sha384 => oaep_key::<sha2::Sha384>(key, config.mgf1_md, label),
+                Digest::Sha512 => oaep_key::<sha2::Sha512>(key, config.mgf1_md, label),
+            }),
+            PaddingMode::None => Key::Raw(key),
+        };
+
+        Ok(RustCryptoBackend {
+            key,
+            size,
+            decrypted: None,
+            rng: rand::thread_rng(),
+        })
+    }
+}
+
+impl Backend for RustCryptoBackend {
+    fn decrypt(&mut self, ciphertext: &[u8], plaintext: &mut [u8]) -> Result<Decryption> {
+        let res = match &self.key {
+            Key::Pkcs1(key) => key
+                .decrypt_with_rng(&mut self.rng, ciphertext)
+                .map(Decrypted::Plaintext),
+            Key::Oaep(key) => key
+                .decrypt(&mut self.rng, ciphertext)
+                .map(Decrypted::Plaintext),
+            Key::Raw(key) => {
+                let c = BigUint::from_bytes_be(ciphertext);
+                hazmat::rsa_decrypt_and_check(key, Some(&mut self.rng), &c).map(Decrypted::Raw)
+            }
+        };
+
+        let decrypted = match res {
+            Ok(decrypted) => decrypted,
+            Err(e) => return Ok(Err(outcome(e)).into()),
+        };
+        let len = match &decrypted {
+            Decrypted::Plaintext(p) => p.len(),
+            // Left padded to the modulus size, like the other backends do
+            Decrypted::Raw(_) => self.size,
+        };
+        self.decrypted = Some(decrypted);
+
+        Ok(Ok(len.min(plaintext.len())).into())
+    }
+
+    fn complete(&mut self, decryption: &mut Decryption, plaintext: &mut [u8]) -> Result<()> {
+        let len = decryption.len;
+
+        match self.decrypted.take() {
+            Some(Decrypted::Plaintext(decrypted)) => {
+                plaintext[..len].copy_from_slice(&decrypted[..len]);
+            }
+            Some(Decrypted::Raw(m)) => {
+                let m = m.to_bytes_be();
+                let offset = self.size.saturating_sub(m.len());
+                for (i, byte) in plaintext[..len].iter_mut().enumerate() {
+                    *byte = i.checked_sub(offset).map_or(0, |i| m[i]);
+                }
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    fn implicit_rejection(&self) -> Option<bool> {
+        Some(false)
+    }
+}
@@ -0,0 +1,435 @@
+//! Compact binary measurement format
+//!
+//! All integers are little endian. The file starts with a header:
+//!
+//! - magic `RDTIMING`, then the `u16` format version
+//! - `u32` number of metadata entries, each a `u16` length prefixed name and
+//!   a `u32` length prefixed value, in UTF-8
+//! - `u32` number of classes, each a `u16` length prefixed UTF-8 name
+//! - `u16` size of the sample records
+//!
+//! and continues with one fixed size record per sample:
+//!
+//! - `u64` index of the ciphertext in the input
+//! - `u64` duration, in the unit given by the `unit` metadata entry
+//! - `u32` class index, [`NO_CLASS`] for unlabelled ciphertexts
+//! - `u32` outcome: 0 success, 1 padding error, 2 other error
+//! - `u32` error code of other errors
+//! - `u32` plaintext length
+//! - `u32` worker of a parallel run, 0 otherwise (since version 2)
+//! - `u32` core of the worker, [`NO_CPU`] when not pinned (since version 2)
+//!
+//! Readers skip the fields of records longer than they know.
+
+use crate::{
+    harness::{Metadata, Sample, Sink},
+    outcome::Outcome,
+};
+use anyhow::{bail, Context, Result};
+use std::io::{ErrorKind, Read, Write};
+
+pub const MAGIC: &[u8; 8] = b"RDTIMING";
+pub const VERSION: u16 = 2;
+
+/// Size of a sample record
+pub const RECORD_SIZE: usize = 40;
+
+/// Size of a sample record in version 1, without worker and core
+const RECORD_SIZE_V1: usize = 32;
+
+/// Class index of unlabelled ciphertexts
+pub const NO_CLASS: u32 = u32::MAX;
+
+/// Core of unpinned workers
+pub const NO_CPU: u32 = u32::MAX;
+
+/// One sample read back from a binary file
+#[derive(Clone, Copy, Debug)]
+pub struct Record {
+    pub index: u64,
+    pub duration: u64,
+    pub class: Option<u32>,
+    pub outcome: Outcome,
+    pub len: u32,
+    pub worker: u32,
+    pub cpu: Option<u32>,
+}
+
+impl Record {
+    fn encode(&self) -> [u8; RECORD_SIZE] {
+        let (outcome, code) = match self.outcome {
+            Outcome::Ok => (0u32, 0u32),
+            Outcome::Padding => (1, 0),
+            Outcome::Error(code) => (2, code),
+        };
+
+        let mut record = [0; RECORD_SIZE];
+        record[0..8].copy_from_slice(&self.index.to_le_bytes());
+        record[8..16].copy_from_slice(&self.duration.to_le_bytes());
+        record[16..20].copy_from_slice(&self.class.unwrap_or(NO_CLASS).to_le_bytes());
+        record[20..24].copy_from_slice(&outcome.to_le_bytes());
+        record[24..28].copy_from_slice(&code.to_le_bytes());
+        record[28..32].copy_from_slice(&self.len.to_le_bytes());
+        record[32..36].copy_from_slice(&self.worker.to_le_bytes());
+        record[36..40].copy_from_slice(&self.cpu.unwrap_or(NO_CPU).to_le_bytes());
+        record
+    }
+
+    fn decode(record: &[u8]) -> Result<Self> {
+        let u32_at = |i: usize| u32::from_le_bytes(record[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(record[i..i + 8].try_into().unwrap());
+
+        let outcome = match u32_at(20) {
+            0 => Outcome::Ok,
+            1 => Outcome::Padding,
+            2 => Outcome::Error(u32_at(24)),
+            o => bail!("Invalid outcome {o} in binary record"),
+        };
+
+        Ok(Record {
+            index: u64_at(0),
+            duration: u64_at(8),
+            class: Some(u32_at(16)).filter(|&c| c != NO_CLASS),
+            outcome,
+            len: u32_at(28),
+            worker: if record.len() >= RECORD_SIZE {
+                u32_at(32)
+            } else {
+                0
+            },
+            cpu: if record.len() >= RECORD_SIZE {
+                Some(u32_at(36)).filter(|&c| c != NO_CPU)
+            } else {
+                None
+            },
+        })
+    }
+
+    /// The record as a sample of the harness
+    pub fn sample(&self) -> Sample {
+        Sample {
+            index: self.index as usize,
+            duration: self.duration,
+            outcome: self.outcome,
+            len: self.len as usize,
+            worker: self.worker as usize,
+            cpu: self.cpu.map(|c| c as usize),
+        }
+    }
+}
+
+fn write_str<W: Write>(writer: &mut W, s: &str, wide: bool) -> std::io::Result<()> {
+    if wide {
+        writer.write_all(&(s.len() as u32).to_le_bytes())?;
+    } else {
+        writer.write_all(&(s.len() as u16).to_le_bytes())?;
+    }
+    writer.write_all(s.as_bytes())
+}
+
+fn read_str<R: Read>(reader: &mut R, wide: bool) -> Result<String> {
+    let len = if wide {
+        read_u32(reader)? as usize
+    } else {
+        read_u16(reader)? as usize
+    };
+
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    String::from_utf8(buf).context("Invalid UTF-8 in binary header")
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Write samples in the binary format
+pub struct BinarySink<W> {
+    writer: W,
+    classes: Vec<String>,
+    /// Class index of every ciphertext in the input
+    labels: Vec<usize>,
+    header: bool,
+}
+
+impl<W: Write> BinarySink<W> {
+    /// `labels` holds the index in `classes` of every ciphertext, both are
+    /// empty for unlabelled inputs
+    pub fn new(writer: W, classes: Vec<String>, labels: Vec<usize>) -> Self {
+        BinarySink {
+            writer,
+            classes,
+            labels,
+            header: true,
+        }
+    }
+
+    /// Only write records, to append to the output of an earlier run
+    pub fn without_header(mut self) -> Self {
+        self.header = false;
+        self
+    }
+}
+
+impl<W: Write> Sink for BinarySink<W> {
+    fn begin(&mut self, metadata: &Metadata) -> Result<()> {
+        if !self.header {
+            return Ok(());
+        }
+
+        let entries: Vec<(&str, &str)> = metadata.iter().collect();
+
+        let mut header = Vec::new();
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for (name, value) in entries {
+            write_str(&mut header, name, false)?;
+            write_str(&mut header, value, true)?;
+        }
+        header.extend_from_slice(&(self.classes.len() as u32).to_le_bytes());
+        for class in &self.classes {
+            write_str(&mut header, class, false)?;
+        }
+        header.extend_from_slice(&(RECORD_SIZE as u16).to_le_bytes());
+
+        self.writer
+            .write_all(&header)
+            .context("failed to write output header")
+    }
+
+    fn record(&mut self, sample: &Sample, _plaintext: &[u8]) -> Result<()> {
+        let record = Record {
+            index: sample.index as u64,
+            duration: sample.duration,
+            class: self.labels.get(sample.index).map(|&c| c as u32),
+            outcome: sample.outcome,
+            len: sample.len as u32,
+            worker: sample.worker as u32,
+            cpu: sample.cpu.map(|c| c as u32),
+        };
+
+        self.writer
+            .write_all(&record.encode())
+            .context("failed to write sample")
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush().context("failed to write sample")
+    }
+}
+
+/// Read a binary measurement file: the header, then the records one by one
+pub struct BinaryReader<R> {
+    reader: R,
+    metadata: Metadata,
+    classes: Vec<String>,
+    record_size: usize,
+}
+
+impl<R: Read> BinaryReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("Failed to read binary header")?;
+        if &magic != MAGIC {
+            bail!("Not a binary measurement file");
+        }
+
+        let version = read_u16(&mut reader)?;
+        if !(1..=VERSION).contains(&version) {
+            bail!("Unsupported binary format version {version}");
+        }
+
+        let mut metadata = Metadata::default();
+        for _ in 0..read_u32(&mut reader)? {
+            let name = read_str(&mut reader, false)?;
+            let value = read_str(&mut reader, true)?;
+            metadata.push(&name, value);
+        }
+
+        let mut classes = Vec::new();
+        for _ in 0..read_u32(&mut reader)? {
+            classes.push(read_str(&mut reader, false)?);
+        }
+
+        // Later versions may append fields to the records
+        let record_size = read_u16(&mut reader)? as usize;
+        let known = if version == 1 {
+            RECORD_SIZE_V1
+        } else {
+            RECORD_SIZE
+        };
+        if record_size < known {
+            bail!("Invalid binary record size {record_size}");
+        }
+
+        Ok(BinaryReader {
+            reader,
+            metadata,
+            classes,
+            record_size,
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = vec![0; self.record_size];
+
+        match self.reader.read_exact(&mut record) {
+            Ok(()) => Some(Record::decode(&record)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e).context("Failed to read binary record")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(index: usize, outcome: Outcome, worker: usize, cpu: Option<usize>) -> Sample {
+        Sample {
+            index,
+            duration: 1000 + index as u64,
+            outcome,
+            len: if outcome == Outcome::Ok { 48 } else { 0 },
+            worker,
+            cpu,
+        }
+    }
+
+    /// Header of an empty file with the given version and record size
+    fn header(version: u16, record_size: usize) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&version.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&(record_size as u16).to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn round_trip() {
+        let samples = [
+            sample(0, Outcome::Ok, 0, None),
+            sample(1, Outcome::Padding, 1, Some(3)),
+            sample(2, Outcome::Error(7), 2, Some(5)),
+        ];
+        let mut metadata = Metadata::default();
+        metadata.push("unit", "ns");
+        metadata.push("clock", "instant");
+
+        let mut file = Vec::new();
+        let mut sink = BinarySink::new(&mut file, vec!["a".into(), "b".into()], vec![0, 1]);
+        sink.begin(&metadata).unwrap();
+        for sample in &samples {
+            sink.record(sample, &[]).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let reader = BinaryReader::new(file.as_slice()).unwrap();
+        assert_eq!(
+            reader.metadata().iter().collect::<Vec<_>>(),
+            [("unit", "ns"), ("clock", "instant")]
+        );
+        assert_eq!(reader.classes(), ["a", "b"]);
+
+        let records = reader.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(records.len(), samples.len());
+        for (record, expected) in records.iter().zip(&samples) {
+            let sample = record.sample();
+            assert_eq!(sample.index, expected.index);
+            assert_eq!(sample.duration, expected.duration);
+            assert_eq!(sample.outcome, expected.outcome);
+            assert_eq!(sample.len, expected.len);
+            assert_eq!(sample.worker, expected.worker);
+            assert_eq!(sample.cpu, expected.cpu);
+        }
+        assert_eq!(records[0].class, Some(0));
+        assert_eq!(records[1].class, Some(1));
+        // Beyond the labels
+        assert_eq!(records[2].class, None);
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let record = Record {
+            index: 4,
+            duration: 99,
+            class: Some(1),
+            outcome: Outcome::Padding,
+            len: 0,
+            worker: 0,
+            cpu: None,
+        };
+        let mut file = header(1, RECORD_SIZE_V1);
+        file.extend_from_slice(&record.encode()[..RECORD_SIZE_V1]);
+
+        let records = BinaryReader::new(file.as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].index, 4);
+        assert_eq!(records[0].duration, 99);
+        assert_eq!(records[0].class, Some(1));
+        assert_eq!(records[0].outcome, Outcome::Padding);
+        assert_eq!(records[0].worker, 0);
+        assert_eq!(records[0].cpu, None);
+    }
+
+    #[test]
+    fn skips_unknown_fields() {
+        let record = Record {
+            index: 1,
+            duration: 10,
+            class: None,
+            outcome: Outcome::Ok,
+            len: 16,
+            worker: 2,
+            cpu: Some(6),
+        };
+        let mut file = header(VERSION, RECORD_SIZE + 8);
+        for _ in 0..2 {
+            file.extend_from_slice(&record.encode());
+            file.extend_from_slice(&[0xff; 8]);
+        }
+
+        let records = BinaryReader::new(file.as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].worker, 2);
+        assert_eq!(records[1].cpu, Some(6));
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert!(BinaryReader::new(&b"NOTATIME\x02\x00"[..]).is_err());
+        assert!(BinaryReader::new(header(VERSION + 1, RECORD_SIZE).as_slice()).is_err());
+        assert!(BinaryReader::new(header(VERSION, RECORD_SIZE_V1).as_slice()).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{bail, Context, Result};
+use openssl::sha::Sha256;
+use serde_json::{json, Map, Value};
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+};
+
+/// State of an interrupted measure run, written next to its output file
+#[derive(Clone, Debug, Default)]
+pub struct Checkpoint {
+    /// SHA-256 of the key file
+    pub key_sha256: String,
+    /// SHA-256 of the input file
+    pub input_sha256: String,
+    /// SHA-256 of the labels file, when the run has one
+    pub labels_sha256: Option<String>,
+    pub format: String,
+    pub repeat: u64,
+    pub shuffle_seed: Option<u64>,
+    /// Number of decryptions done, recorded or discarded, which is the
+    /// position in the schedule or the input
+    pub position: usize,
+    /// Number of recorded samples
+    pub samples: usize,
+    /// Number of discarded samples
+    pub discarded: usize,
+    /// Length of the output file holding exactly `samples` samples
+    pub output_len: u64,
+    /// Length of the plaintext file, when the run writes one
+    pub plaintext_len: Option<u64>,
+    /// Options which must not change when the run is resumed, by name
+    pub settings: Vec<(String, String)>,
+    /// State of the verifier, when the run has one
+    pub verifier: Option<Value>,
+}
+
+/// Path of the checkpoint of an output file
+pub fn path(output: &str) -> String {
+    format!("{output}.checkpoint")
+}
+
+/// SHA-256 of a file, hex encoded
+pub fn file_sha256(path: &str) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {path}"))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 16];
+
+    loop {
+        match file
+            .read(&mut buf)
+            .with_context(|| format!("Failed to read {path}"))?
+        {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+
+    Ok(hex::encode(hasher.finish()))
+}
+
+impl Checkpoint {
+    pub fn write(&self, path: &str) -> Result<()> {
+        let checkpoint = json!({
+            "key_sha256": self.key_sha256,
+            "input_sha256": self.input_sha256,
+            "labels_sha256": self.labels_sha256,
+            "format": self.format,
+            "repeat": self.repeat,
+            "shuffle_seed": self.shuffle_seed,
+            "position": self.position,
+            "samples": self.samples,
+            "discarded": self.discarded,
+            "output_len": self.output_len,
+            "plaintext_len": self.plaintext_len,
+            "settings": self
+                .settings
+                .iter()
+                .map(|(name, value)| (name.clone(), Value::from(value.as_str())))
+                .collect::<Map<String, Value>>(),
+            "verifier": self.verifier,
+        });
+
+        let text = serde_json::to_string_pretty(&checkpoint)?;
+        std::fs::write(path, text + "\n").context("failed to write checkpoint")
+    }
+
+    pub fn read(path: &str) -> Result<Self> {
+        let file = File::open(path).context("Failed to open checkpoint file")?;
+        let value: Value =
+            serde_json::from_reader(BufReader::new(file)).context("Invalid checkpoint file")?;
+
+        let string = |name: &str| -> Result<String> {
+            value[name]
+                .as_str()
+                .map(str::to_owned)
+                .with_context(|| format!("No {name} in checkpoint file"))
+        };
+        let number = |name: &str| -> Result<u64> {
+            value[name]
+                .as_u64()
+                .with_context(|| format!("No {name} in checkpoint file"))
+        };
+
+        Ok(Checkpoint {
+            key_sha256: string("key_sha256")?,
+            input_sha256: string("input_sha256")?,
+            labels_sha256: value["labels_sha256"].as_str().map(str::to_owned),
+            format: string("format")?,
+            repeat: number("repeat")?,
+            shuffle_seed: value["shuffle_seed"].as_u64(),
+            position: number("position")? as usize,
+            samples: number("samples")? as usize,
+            discarded: number("discarded")? as usize,
+            output_len: number("output_len")?,
+            plaintext_len: value["plaintext_len"].as_u64(),
+            settings: value["settings"]
+                .as_object()
+                .context("No settings in checkpoint file")?
+                .iter()
+                .map(|(name, value)| {
+                    let value = value
+                        .as_str()
+                        .context("Invalid setting in checkpoint file")?;
+                    Ok((name.clone(), value.to_owned()))
+                })
+                .collect::<Result<_>>()?,
+            verifier: Some(value["verifier"].clone()).filter(|v| !v.is_null()),
+        })
+    }
+
+    /// Check that the options of the resumed run are those of the
+    /// checkpoint
+    pub fn verify_settings(&self, settings: &[(String, String)]) -> Result<()> {
+        for (name, value) in settings {
+            match self.settings.iter().find(|(n, _)| n == name) {
+                Some((_, v)) if v == value => (),
+                Some((_, v)) => bail!("The run used --{name} {v}, it cannot be resumed"),
+                None => bail!("The checkpoint does not record --{name}, it cannot be resumed"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that a file has the hash recorded in the checkpoint
+    pub fn verify(what: &str, path: &str, expected: &str) -> Result<()> {
+        if file_sha256(path)? != expected {
+            bail!("The {what} file {path} changed since the checkpoint, it cannot be resumed");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checkpoint file in the temporary directory, removed on drop
+    struct TempPath(String);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "rsa-decrypt-timing-{}-{name}.checkpoint",
+                std::process::id()
+            ));
+            TempPath(path.to_str().unwrap().to_owned())
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn settings(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect()
+    }
+
+    fn checkpoint() -> Checkpoint {
+        Checkpoint {
+            key_sha256: "aa".repeat(32),
+            input_sha256: "bb".repeat(32),
+            labels_sha256: Some("cc".repeat(32)),
+            format: "binary".to_owned(),
+            repeat: 3,
+            shuffle_seed: Some(u64::MAX),
+            position: 1500,
+            samples: 1490,
+            discarded: 10,
+            output_len: 1 << 40,
+            plaintext_len: Some(4096),
+            settings: settings(&[("backend", "openssl"), ("padding", "pkcs1")]),
+            verifier: Some(json!({ "checked": 7, "seen": { "0": "ok" } })),
+        }
+    }
+
+    fn assert_same(a: &Checkpoint, b: &Checkpoint) {
+        assert_eq!(a.key_sha256, b.key_sha256);
+        assert_eq!(a.input_sha256, b.input_sha256);
+        assert_eq!(a.labels_sha256, b.labels_sha256);
+        assert_eq!(a.format, b.format);
+        assert_eq!(a.repeat, b.repeat);
+        assert_eq!(a.shuffle_seed, b.shuffle_seed);
+        assert_eq!(a.position, b.position);
+        assert_eq!(a.samples, b.samples);
+        assert_eq!(a.discarded, b.discarded);
+        assert_eq!(a.output_len, b.output_len);
+        assert_eq!(a.plaintext_len, b.plaintext_len);
+        assert_eq!(a.settings, b.settings);
+        assert_eq!(a.verifier, b.verifier);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let path = TempPath::new("round-trip");
+        let checkpoint = checkpoint();
+
+        checkpoint.write(&path.0).unwrap();
+        assert_same(&Checkpoint::read(&path.0).unwrap(), &checkpoint);
+    }
+
+    #[test]
+    fn optional_fields_are_null() {
+        let path = TempPath::new("null");
+        let checkpoint = Checkpoint {
+            labels_sha256: None,
+            shuffle_seed: None,
+            plaintext_len: None,
+            verifier: None,
+            ..checkpoint()
+        };
+
+        checkpoint.write(&path.0).unwrap();
+        let value: Value =
+            serde_json::from_str(&std::fs::read_to_string(&path.0).unwrap()).unwrap();
+        for name in ["labels_sha256", "shuffle_seed", "plaintext_len", "verifier"] {
+            assert!(value[name].is_null(), "{name}");
+        }
+        assert_same(&Checkpoint::read(&path.0).unwrap(), &checkpoint);
+    }
+
+    #[test]
+    fn read_rejects_incomplete_checkpoints() {
+        let path = TempPath::new("incomplete");
+
+        std::fs::write(&path.0, "{}").unwrap();
+        assert!(Checkpoint::read(&path.0).is_err());
+
+        std::fs::write(&path.0, "not json").unwrap();
+        assert!(Checkpoint::read(&path.0).is_err());
+    }
+
+    #[test]
+    fn settings_must_match() {
+        let checkpoint = checkpoint();
+
+        checkpoint.verify_settings(&[]).unwrap();
+        checkpoint
+            .verify_settings(&settings(&[("padding", "pkcs1"), ("backend", "openssl")]))
+            .unwrap();
+
+        let changed = checkpoint
+            .verify_settings(&settings(&[("backend", "rustcrypto")]))
+            .unwrap_err();
+        assert_eq!(
+            changed.to_string(),
+            "The run used --backend openssl, it cannot be resumed"
+        );
+
+        let missing = checkpoint
+            .verify_settings(&settings(&[("backend", "openssl"), ("label", "00")]))
+            .unwrap_err();
+        assert_eq!(
+            missing.to_string(),
+            "The checkpoint does not record --label, it cannot be resumed"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use std::{fmt, str::FromStr, time::Instant};
+
+#[cfg(target_os = "linux")]
+use std::os::fd::{FromRawFd, OwnedFd};
+
+/// Number of back-to-back readings used to estimate the clock overhead
+const OVERHEAD_ROUNDS: usize = 10000;
+
+/// Source of the timestamps taken around each decryption
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockSource {
+    /// std::time::Instant
+    Instant,
+    /// Time stamp counter read with rdtscp, fenced with lfence (x86_64)
+    Rdtscp,
+    /// Virtual counter cntvct_el0, fenced with isb (aarch64)
+    Cntvct,
+    /// clock_gettime(CLOCK_MONOTONIC_RAW)
+    MonotonicRaw,
+    /// CPU time consumed by the measuring thread
+    ThreadCputime,
+    /// CPU cycle counter from perf_event_open
+    PerfCycles,
+}
+
+impl fmt::Display for ClockSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+/// Unit of the durations produced by a clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    Nanoseconds,
+    Ticks,
+    Cycles,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unit::Nanoseconds => write!(f, "ns"),
+            Unit::Ticks => write!(f, "ticks"),
+            Unit::Cycles => write!(f, "cycles"),
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ns" => Ok(Unit::Nanoseconds),
+            "ticks" => Ok(Unit::Ticks),
+            "cycles" => Ok(Unit::Cycles),
+            _ => bail!("Unknown duration unit {s}"),
+        }
+    }
+}
+
+/// Clock used to time decryptions
+///
+/// Timestamps must be taken with [`Clock::start`] before and [`Clock::stop`]
+/// after the measured code, as the fencing differs on each side.
+pub struct Clock {
+    source: ClockSource,
+    base: Instant,
+    #[cfg(target_os = "linux")]
+    perf: Option<OwnedFd>,
+}
+
+impl Clock {
+    pub fn new(source: ClockSource) -> Result<Self> {
+        match source {
+            ClockSource::Rdtscp if !has_rdtscp() => {
+                bail!("The rdtscp clock is only available on x86_64 CPUs supporting rdtscp")
+            }
+            ClockSource::Cntvct if !cfg!(target_arch = "aarch64") => {
+                bail!("The cntvct clock is only available on aarch64")
+            }
+            ClockSource::MonotonicRaw | ClockSource::ThreadCputime | ClockSource::PerfCycles
+                if !cfg!(target_os = "linux") =>
+            {
+                bail!("The {source} clock is only available on Linux")
+            }
+            _ => (),
+        }
+
+        Ok(Clock {
+            source,
+            base: Instant::now(),
+            #[cfg(target_os = "linux")]
+            perf: match source {
+                ClockSource::PerfCycles => Some(perf::open_cycle_counter()?),
+                _ => None,
+            },
+        })
+    }
+
+    pub fn source(&self) -> ClockSource {
+        self.source
+    }
+
+    pub fn unit(&self) -> Unit {
+        match self.source {
+            ClockSource::Instant | ClockSource::MonotonicRaw | ClockSource::ThreadCputime => {
+                Unit::Nanoseconds
+            }
+            ClockSource::Rdtscp | ClockSource::Cntvct => Unit::Ticks,
+            ClockSource::PerfCycles => Unit::Cycles,
+        }
+    }
+
+    /// Timestamp taken before the measured code
+    #[inline(always)]
+    pub fn start(&self) -> u64 {
+        match self.source {
+            #[cfg(target_arch = "x86_64")]
+            ClockSource::Rdtscp => {
+                use std::arch::x86_64::{_mm_lfence, _rdtsc};
+                // SAFETY: lfence and rdtsc are available on every x86_64 CPU
+                unsafe {
+                    _mm_lfence();
+                    let t = _rdtsc();
+                    _mm_lfence();
+                    t
+                }
+            }
+            _ => self.read(),
+        }
+    }
+
+    /// Timestamp taken after the measured code
+    #[inline(always)]
+    pub fn stop(&self) -> u64 {
+        match self.source {
+            #[cfg(target_arch = "x86_64")]
+            ClockSource::Rdtscp => {
+                use std::arch::x86_64::{__rdtscp, _mm_lfence};
+                let mut aux = 0;
+                // SAFETY: rdtscp support is checked in Clock::new, lfence is
+                // available on every x86_64 CPU
+                unsafe {
+                    let t = __rdtscp(&mut aux);
+                    _mm_lfence();
+                    t
+                }
+            }
+            _ => self.read(),
+        }
+    }
+
+    /// Timestamp for the clocks that need no asymmetric fencing
+    #[inline(always)]
+    fn read(&self) -> u64 {
+        match self.source {
+            #[cfg(target_arch = "aarch64")]
+            ClockSource::Cntvct => {
+                let t: u64;
+                // SAFETY: cntvct_el0 is readable from user space on Linux
+                unsafe {
+                    std::arch::asm!(
+                        "isb",
+                        "mrs {t}, cntvct_el0",
+                        "isb",
+                        t = out(reg) t,
+                        options(nostack, nomem),
+                    );
+                }
+                t
+            }
+            #[cfg(target_os = "linux")]
+            ClockSource::MonotonicRaw => clock_gettime(libc::CLOCK_MONOTONIC_RAW),
+            #[cfg(target_os = "linux")]
+            ClockSource::ThreadCputime => clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID),
+            #[cfg(target_os = "linux")]
+            ClockSource::PerfCycles => perf::read(self.perf.as_ref().expect("opened in new")),
+            _ => self.base.elapsed().as_nanos() as u64,
+        }
+    }
+
+    /// Median duration measured around no code at all
+    pub fn overhead(&self) -> u64 {
+        let mut durations: Vec<u64> = (0..OVERHEAD_ROUNDS)
+            .map(|_| {
+                let start = self.start();
+                let stop = self.stop();
+                stop.wrapping_sub(start)
+            })
+            .collect();
+
+        durations.sort_unstable();
+        durations[durations.len() / 2]
+    }
+}
+
+/// Whether the CPU supports the rdtscp instruction
+fn has_rdtscp() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use std::arch::x86_64::__cpuid;
+        __cpuid(0x8000_0000).eax >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 27) != 0
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    false
+}
+
+#[cfg(target_os = "linux")]
+#[inline(always)]
+fn clock_gettime(clock: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: ts is a valid timespec and both clocks exist on Linux
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+#[cfg(target_os = "linux")]
+mod perf {
+    use super::{FromRawFd, OwnedFd};
+    use anyhow::{Context, Result};
+    use std::{io, os::fd::AsRawFd};
+
+    const PERF_TYPE_HARDWARE: u32 = 0;
+    const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+    const PERF_ATTR_SIZE_VER0: u32 = 64;
+    const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 8;
+
+    /// Bits of the perf_event_attr flags bitfield
+    const EXCLUDE_KERNEL: u64 = 1 << 5;
+    const EXCLUDE_HV: u64 = 1 << 6;
+
+    /// First version of struct perf_event_attr, which is all we need
+    #[repr(C)]
+    #[derive(Default)]
+    struct PerfEventAttr {
+        type_: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+    }
+
+    /// Open an enabled user space cycle counter for the calling thread
+    pub fn open_cycle_counter() -> Result<OwnedFd> {
+        let attr = PerfEventAttr {
+            type_: PERF_TYPE_HARDWARE,
+            size: PERF_ATTR_SIZE_VER0,
+            config: PERF_COUNT_HW_CPU_CYCLES,
+            flags: EXCLUDE_KERNEL | EXCLUDE_HV,
+            ..Default::default()
+        };
+
+        // SAFETY: attr is a valid perf_event_attr of the advertised size
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr,
+                0,
+                -1,
+                -1,
+                PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Failed to open perf cycle counter");
+        }
+
+        // SAFETY: the syscall returned a new file descriptor we now own
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+    }
+
+    #[inline(always)]
+    pub fn read(fd: &OwnedFd) -> u64 {
+        let mut count: u64 = 0;
+        // SAFETY: count is a valid 8 byte buffer
+        unsafe {
+            libc::read(
+                fd.as_raw_fd(),
+                &mut count as *mut u64 as *mut libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+        count
+    }
+}
@@ -0,0 +1,144 @@
+use crate::measure;
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use rsa_decrypt_timing::{
+    binary::BinaryReader,
+    clock::Unit,
+    harness::{CsvSink, Sink},
+    measurements::{Layout, Measurements},
+};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+};
+
+/// Convert a binary measure output file
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// Binary output file of a measure run
+    #[arg(short = 'i', long)]
+    input: String,
+
+    /// Converted file
+    #[arg(short = 'o', long)]
+    output: String,
+
+    /// Format of the converted file
+    #[arg(short = 't', long, value_enum, default_value_t = Target::Text)]
+    to: Target,
+}
+
+/// Format a binary file is converted to
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// Text output of the measure subcommand
+    Text,
+    /// `index,class,duration,outcome,length,worker,cpu` table with a header
+    /// row
+    Csv,
+    /// tlsfuzzer `timing.csv`, one column per class
+    Wide,
+    /// tlsfuzzer `measurements.csv`, one `block,group,value` row per sample
+    Long,
+}
+
+type Reader = BinaryReader<BufReader<File>>;
+
+/// Write the records in the text format of the measure subcommand
+fn write_text(reader: Reader, path: &str) -> Result<usize> {
+    let output = File::create(path).context("Failed to create output file")?;
+    let mut csv = CsvSink::new(BufWriter::new(output));
+    let mut count = 0;
+
+    csv.begin(reader.metadata())?;
+    for record in reader {
+        csv.record(&record?.sample(), &[])?;
+        count += 1;
+    }
+    csv.finish()?;
+
+    Ok(count)
+}
+
+/// Write the records as a table with class names
+fn write_csv(reader: Reader, path: &str) -> Result<usize> {
+    let output = File::create(path).context("Failed to create output file")?;
+    let mut output = BufWriter::new(output);
+    let classes = reader.classes().to_vec();
+    let mut count = 0;
+
+    writeln!(
+        &mut output,
+        "index,class,duration,outcome,length,worker,cpu"
+    )
+    .context("failed to write output header")?;
+    for record in reader {
+        let record = record?;
+        let class = match record.class {
+            Some(class) => classes
+                .get(class as usize)
+                .with_context(|| format!("Invalid class index {class}"))?
+                .as_str(),
+            None => "",
+        };
+        let cpu = record.cpu.map_or("-".to_owned(), |c| c.to_string());
+        writeln!(
+            &mut output,
+            "{},{},{},{},{},{},{cpu}",
+            record.index, class, record.duration, record.outcome, record.len, record.worker
+        )
+        .context("failed to write sample")?;
+        count += 1;
+    }
+    output.flush().context("failed to write sample")?;
+
+    Ok(count)
+}
+
+/// Write the records in a tlsfuzzer layout, grouped in tuples of classes
+fn write_layout(reader: Reader, path: &str, layout: Layout, unit: Unit) -> Result<usize> {
+    if reader.classes().is_empty() {
+        bail!("The input has no class labels, it cannot be converted to a tlsfuzzer layout");
+    }
+
+    let mut measurements = Measurements::with_classes(reader.classes().to_vec());
+    let mut count = 0;
+
+    for record in reader {
+        let record = record?;
+        let class = record
+            .class
+            .with_context(|| format!("No class for ciphertext {}", record.index))?;
+        measurements.push_class(class as usize, record.duration)?;
+        count += 1;
+    }
+    measure::write_measurements(&measurements, path, layout, unit)?;
+
+    Ok(count)
+}
+
+pub fn convert(args: &ConvertArgs) -> Result<()> {
+    let file = File::open(&args.input).context("Failed to open input file")?;
+    let reader = BinaryReader::new(BufReader::new(file))?;
+
+    let unit: Unit = reader
+        .metadata()
+        .get("unit")
+        .context("No unit in the input metadata")?
+        .parse()?;
+
+    println!("input: {}", args.input);
+    println!("output: {}", args.output);
+    println!("classes: {}", reader.classes().len());
+
+    let count = match args.to {
+        Target::Text => write_text(reader, &args.output)?,
+        Target::Csv => write_csv(reader, &args.output)?,
+        Target::Wide => write_layout(reader, &args.output, Layout::Wide, unit)?,
+        Target::Long => write_layout(reader, &args.output, Layout::Long, unit)?,
+    };
+
+    println!("samples: {count}");
+
+    Ok(())
+}
@@ -0,0 +1,374 @@
+//! Online leakage detection in the style of dudect
+//!
+//! Welch's t-test is run between every pair of classes on all samples and on
+//! samples cropped at increasing percentiles, which removes the long upper
+//! tail of the timing distribution that hides small differences.
+
+use crate::{
+    harness::{Sample, Sink},
+    outcome::Outcome,
+    stats::{self, Moments},
+};
+use anyhow::Result;
+use std::fmt;
+
+/// Number of cropped variants of the test, as in dudect
+const PERCENTILES: usize = 100;
+
+/// Samples used to place the cropping thresholds
+const CALIBRATION: usize = 1000;
+
+/// Samples between two checks of the largest |t| against the threshold
+const CHECK_EVERY: usize = 100;
+
+/// Samples per class needed before the test can stop the run
+pub const MIN_SAMPLES: u64 = 1000;
+
+/// Samples between two progress reports of the largest |t|
+pub const REPORT_EVERY: usize = 10000;
+
+/// |t| above which dudect reports "definitely not constant time"
+pub const DEFAULT_THRESHOLD: f64 = 10.0;
+
+/// Largest |t| over all pairs of classes and cropped variants
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaxT {
+    pub t: f64,
+    /// Classes of the pair
+    pub a: usize,
+    pub b: usize,
+    /// Cropping threshold, `None` for the test on all samples
+    pub crop: Option<f64>,
+}
+
+/// Streaming t-tests between classes, fed by the samples of a run
+///
+/// The class of a sample is its label, or its outcome (success or failure)
+/// for unlabelled inputs.
+pub struct Dudect {
+    classes: Vec<String>,
+    /// Class index of every ciphertext in the input, empty to use outcomes
+    labels: Vec<usize>,
+    /// |t| above which a leak is detected and the run stops
+    threshold: f64,
+    /// Number of samples after which the run stops
+    budget: Option<usize>,
+    /// Durations of the first samples, with their class, until the
+    /// thresholds are placed
+    calibration: Vec<(usize, f64)>,
+    thresholds: Vec<f64>,
+    /// Moments of every class, on all samples then on every cropped variant
+    moments: Vec<[Moments; PERCENTILES + 1]>,
+    count: usize,
+    /// Largest |t| when it first went over the threshold
+    leak: Option<MaxT>,
+}
+
+impl Dudect {
+    /// `labels` holds the index in `classes` of every ciphertext, both are
+    /// empty to compare successful and failed decryptions
+    pub fn new(
+        classes: Vec<String>,
+        labels: Vec<usize>,
+        threshold: f64,
+        budget: Option<usize>,
+    ) -> Self {
+        let classes = if labels.is_empty() {
+            vec!["ok".to_owned(), "failed".to_owned()]
+        } else {
+            classes
+        };
+
+        Dudect {
+            moments: vec![[Moments::default(); PERCENTILES + 1]; classes.len()],
+            classes,
+            labels,
+            threshold,
+            budget,
+            calibration: Vec::with_capacity(CALIBRATION),
+            thresholds: Vec::new(),
+            count: 0,
+            leak: None,
+        }
+    }
+
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Largest |t| at the moment it went over the threshold with enough
+    /// samples in every class, `None` when it never did
+    ///
+    /// Buffered runs only stop between chunks, by which time the largest
+    /// |t| may have dropped again.
+    pub fn leak(&self) -> Option<MaxT> {
+        self.leak
+    }
+
+    /// Classes with too few samples for the test to conclude
+    pub fn short_classes(&self) -> Vec<&str> {
+        self.classes
+            .iter()
+            .zip(&self.moments)
+            .filter(|(_, m)| m[0].count() < MIN_SAMPLES)
+            .map(|(class, _)| class.as_str())
+            .collect()
+    }
+
+    fn class(&self, sample: &Sample) -> Option<usize> {
+        if self.labels.is_empty() {
+            Some(usize::from(sample.outcome != Outcome::Ok))
+        } else {
+            self.labels.get(sample.index).copied()
+        }
+    }
+
+    /// Place the thresholds at the percentiles dudect uses, which get denser
+    /// towards the lower end of the distribution
+    fn calibrate(&mut self) {
+        let mut sorted: Vec<f64> = self.calibration.iter().map(|&(_, d)| d).collect();
+        sorted.sort_unstable_by(f64::total_cmp);
+
+        self.thresholds = (0..PERCENTILES)
+            .map(|i| {
+                let q = 1.0 - 0.5f64.powf(10.0 * (i + 1) as f64 / PERCENTILES as f64);
+                stats::quantile(&sorted, q)
+            })
+            .collect();
+
+        for (class, duration) in std::mem::take(&mut self.calibration) {
+            self.add_cropped(class, duration);
+        }
+    }
+
+    fn add_cropped(&mut self, class: usize, duration: f64) {
+        let moments = &mut self.moments[class];
+        for (i, &threshold) in self.thresholds.iter().enumerate() {
+            if duration < threshold {
+                moments[i + 1].push(duration);
+            }
+        }
+    }
+
+    /// Largest |t| over all pairs of classes and cropped variants
+    pub fn max_t(&self) -> MaxT {
+        let mut max = MaxT {
+            b: usize::from(self.classes.len() > 1),
+            ..MaxT::default()
+        };
+
+        for a in 0..self.classes.len() {
+            for b in a + 1..self.classes.len() {
+                for test in 0..=self.thresholds.len() {
+                    let t = stats::welch_t(&self.moments[a][test], &self.moments[b][test]).abs();
+                    if t > max.t {
+                        max = MaxT {
+                            t,
+                            a,
+                            b,
+                            crop: test.checked_sub(1).map(|i| self.thresholds[i]),
+                        };
+                    }
+                }
+            }
+        }
+
+        max
+    }
+
+    /// Whether every class has enough samples to trust the statistic
+    fn enough_samples(&self) -> bool {
+        self.moments.iter().all(|m| m[0].count() >= MIN_SAMPLES)
+    }
+
+    /// Record the largest |t| if it is the first over the threshold
+    fn check(&mut self) {
+        if self.leak.is_none() && self.enough_samples() {
+            let max = self.max_t();
+            if max.t > self.threshold {
+                self.leak = Some(max);
+            }
+        }
+    }
+
+    /// Description of the largest |t|, with class names
+    pub fn describe(&self, max: &MaxT) -> String {
+        format!(
+            "{:.2} ({} vs {}, {})",
+            max.t,
+            self.classes[max.a],
+            self.classes[max.b],
+            Crop(max.crop)
+        )
+    }
+}
+
+/// Cropping threshold of a test, for display
+struct Crop(Option<f64>);
+
+impl fmt::Display for Crop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(threshold) => write!(f, "below {threshold:.0}"),
+            None => write!(f, "all samples"),
+        }
+    }
+}
+
+impl Sink for Dudect {
+    fn record(&mut self, sample: &Sample, _plaintext: &[u8]) -> Result<()> {
+        self.count += 1;
+
+        let Some(class) = self.class(sample) else {
+            return Ok(());
+        };
+        let duration = sample.duration as f64;
+
+        self.moments[class][0].push(duration);
+        if self.thresholds.is_empty() {
+            self.calibration.push((class, duration));
+            if self.calibration.len() == CALIBRATION {
+                self.calibrate();
+            }
+        } else {
+            self.add_cropped(class, duration);
+        }
+
+        // Checking every sample would cost more than the decryption
+        if self.count.is_multiple_of(CHECK_EVERY) {
+            self.check();
+        }
+
+        Ok(())
+    }
+
+    fn done(&self) -> bool {
+        self.leak.is_some() || self.budget.is_some_and(|budget| self.count >= budget)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // Short runs never reach the calibration size
+        if self.thresholds.is_empty() && !self.calibration.is_empty() {
+            self.calibrate();
+        }
+        self.check();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(index: usize, duration: u64, outcome: Outcome) -> Sample {
+        Sample {
+            index,
+            duration,
+            outcome,
+            len: 0,
+            worker: 0,
+            cpu: None,
+        }
+    }
+
+    /// Two labelled classes taken in turn, the second `shift` slower
+    fn labelled(shift: u64, budget: Option<usize>) -> (Dudect, impl FnMut(&mut Dudect)) {
+        let n = 10 * MIN_SAMPLES as usize;
+        let dudect = Dudect::new(
+            vec!["a".to_owned(), "b".to_owned()],
+            (0..n).map(|i| i % 2).collect(),
+            DEFAULT_THRESHOLD,
+            budget,
+        );
+        let mut index = 0;
+        let next = move |dudect: &mut Dudect| {
+            let noise = (index as u64 / 2 * 7919) % 97;
+            let duration = 1000 + noise + shift * (index % 2) as u64;
+            dudect
+                .record(&sample(index, duration, Outcome::Ok), &[])
+                .unwrap();
+            index += 1;
+        };
+        (dudect, next)
+    }
+
+    #[test]
+    fn shifted_classes_leak_once_both_have_enough_samples() {
+        let (mut dudect, mut next) = labelled(50, None);
+
+        for _ in 0..2 * MIN_SAMPLES - 1 {
+            next(&mut dudect);
+        }
+        assert!(dudect.max_t().t > DEFAULT_THRESHOLD);
+        assert!(dudect.leak().is_none());
+        assert!(!dudect.done());
+
+        next(&mut dudect);
+        let leak = dudect.leak().unwrap();
+        assert!(leak.t > DEFAULT_THRESHOLD);
+        assert_eq!((leak.a, leak.b), (0, 1));
+        assert!(dudect.done());
+    }
+
+    #[test]
+    fn identical_classes_do_not_leak() {
+        let (mut dudect, mut next) = labelled(0, None);
+
+        for _ in 0..4 * MIN_SAMPLES {
+            next(&mut dudect);
+        }
+        dudect.finish().unwrap();
+        assert!(dudect.max_t().t < DEFAULT_THRESHOLD);
+        assert!(dudect.leak().is_none());
+        assert!(dudect.short_classes().is_empty());
+    }
+
+    #[test]
+    fn budget_stops_the_run() {
+        let (mut dudect, mut next) = labelled(0, Some(500));
+
+        for _ in 0..499 {
+            next(&mut dudect);
+        }
+        assert!(!dudect.done());
+        next(&mut dudect);
+        assert!(dudect.done());
+        assert_eq!(dudect.count(), 500);
+    }
+
+    #[test]
+    fn short_runs_report_their_short_classes() {
+        let mut dudect = Dudect::new(Vec::new(), Vec::new(), DEFAULT_THRESHOLD, None);
+        assert_eq!(dudect.classes(), ["ok", "failed"]);
+
+        for i in 0..MIN_SAMPLES as usize {
+            dudect.record(&sample(i, 1000, Outcome::Ok), &[]).unwrap();
+        }
+        for i in 0..10 {
+            dudect
+                .record(&sample(i, 5000, Outcome::Padding), &[])
+                .unwrap();
+        }
+        dudect.finish().unwrap();
+
+        assert_eq!(dudect.short_classes(), ["failed"]);
+        assert!(dudect.leak().is_none());
+        assert!(!dudect.done());
+    }
+
+    #[test]
+    fn unlabelled_ciphertexts_are_counted_but_not_tested() {
+        let mut dudect = Dudect::new(vec!["a".to_owned()], vec![0], DEFAULT_THRESHOLD, None);
+
+        dudect.record(&sample(0, 1000, Outcome::Ok), &[]).unwrap();
+        dudect.record(&sample(1, 1000, Outcome::Ok), &[]).unwrap();
+        dudect.finish().unwrap();
+
+        assert_eq!(dudect.count(), 2);
+        assert_eq!(dudect.short_classes(), ["a"]);
+    }
+}
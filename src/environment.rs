@@ -0,0 +1,109 @@
+//! Description of the machine and libraries a run was measured with
+
+use serde_json::{json, Value};
+use std::{
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Read a small text file, trimmed, or `None` when it cannot be read
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_owned())
+}
+
+/// Value of a field of the first processor in `/proc/cpuinfo`
+fn cpuinfo_field(cpuinfo: &str, name: &str) -> Option<String> {
+    cpuinfo
+        .lines()
+        .take_while(|line| !line.trim().is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim() == name)
+        .map(|(_, value)| value.trim().to_owned())
+}
+
+/// Turbo/boost state, from intel_pstate or the generic cpufreq boost switch
+fn turbo() -> Option<bool> {
+    if let Some(no_turbo) = read_trimmed("/sys/devices/system/cpu/intel_pstate/no_turbo") {
+        return Some(no_turbo == "0");
+    }
+    read_trimmed("/sys/devices/system/cpu/cpufreq/boost").map(|boost| boost == "1")
+}
+
+/// OpenSSL library the binary runs against
+pub fn openssl() -> Value {
+    json!({
+        "version": openssl::version::version(),
+        "number": format!("{:#010x}", openssl::version::number()),
+        "built_on": openssl::version::built_on(),
+        "platform": openssl::version::platform(),
+        "c_flags": openssl::version::c_flags(),
+        "dir": openssl::version::dir(),
+    })
+}
+
+/// Frequency scaling state of one CPU
+fn cpufreq(cpu: usize) -> Value {
+    let cpufreq = format!("/sys/devices/system/cpu/cpu{cpu}/cpufreq");
+
+    json!({
+        "cpu": cpu,
+        "driver": read_trimmed(&format!("{cpufreq}/scaling_driver")),
+        "governor": read_trimmed(&format!("{cpufreq}/scaling_governor")),
+        "min_khz": read_trimmed(&format!("{cpufreq}/scaling_min_freq")),
+        "max_khz": read_trimmed(&format!("{cpufreq}/scaling_max_freq")),
+    })
+}
+
+/// CPU, kernel and frequency scaling state, for the CPUs measured on
+pub fn system(cpus: &[usize]) -> Value {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+
+    json!({
+        "cpu": {
+            "model": cpuinfo_field(&cpuinfo, "model name"),
+            "microcode": cpuinfo_field(&cpuinfo, "microcode"),
+            "online": read_trimmed("/sys/devices/system/cpu/online"),
+            "isolated": read_trimmed("/sys/devices/system/cpu/isolated"),
+            "nohz_full": read_trimmed("/sys/devices/system/cpu/nohz_full"),
+        },
+        "kernel": {
+            "release": read_trimmed("/proc/sys/kernel/osrelease"),
+            "version": read_trimmed("/proc/sys/kernel/version"),
+            "cmdline": read_trimmed("/proc/cmdline"),
+        },
+        "cpufreq": cpus.iter().map(|&cpu| cpufreq(cpu)).collect::<Vec<_>>(),
+        "turbo": turbo(),
+        "smt": {
+            "control": read_trimmed("/sys/devices/system/cpu/smt/control"),
+            "active": read_trimmed("/sys/devices/system/cpu/smt/active").map(|a| a == "1"),
+        },
+    })
+}
+
+/// Current UTC time in RFC 3339 format
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let (days, time) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
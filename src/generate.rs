@@ -0,0 +1,341 @@
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use openssl::{
+    bn::{BigNum, BigNumRef},
+    pkey::{PKey, Public},
+    rsa::{Padding, Rsa},
+};
+use rand::{seq::SliceRandom, Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+/// Length of the message used by the malformed probes
+const PROBE_MESSAGE_LEN: usize = 48;
+
+/// Minimum length of the PKCS#1 v1.5 padding string
+const MIN_PADDING_LEN: usize = 8;
+
+/// Generate PKCS1 ciphertexts for timing measurements
+#[derive(Args, Debug)]
+pub struct GenerateArgs {
+    /// Key file (PEM private or public key)
+    #[arg(short = 'k', long)]
+    key: String,
+
+    /// Output ciphertext file
+    #[arg(short = 'o', long)]
+    output: String,
+
+    /// Output class label file, one label per ciphertext
+    #[arg(short = 'l', long)]
+    labels: String,
+
+    /// Number of ciphertexts to generate for each class
+    #[arg(short = 'n', long, default_value_t = 10000)]
+    count: usize,
+
+    /// Classes to generate (defaults to all)
+    #[arg(short = 'c', long, value_enum, value_delimiter = ',')]
+    classes: Vec<ProbeClass>,
+
+    /// Seed for the random generator, for reproducible output
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Output expected plaintext file, one hex plaintext per ciphertext, or
+    /// `-` when the decryption must fail
+    #[arg(short = 'e', long)]
+    expected: Option<String>,
+}
+
+/// Class of probe ciphertext, named after the plaintext structure
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeClass {
+    /// Random value below the modulus
+    NoStructure,
+    /// Valid padding with an empty message
+    Valid0,
+    /// Valid padding with a 48 byte message
+    Valid48,
+    /// Valid padding with the largest possible message
+    ValidMax,
+    /// First byte set to 0x01 instead of 0x00
+    WrongFirstByte,
+    /// Block type 0x01 instead of 0x02
+    WrongBlockType,
+    /// No zero byte separating the padding from the message
+    NoSeparator,
+    /// Zero byte in the middle of the minimum padding
+    ZeroInPadding,
+    /// Padding one byte shorter than the minimum
+    ShortPadding,
+}
+
+impl ProbeClass {
+    /// Label written to the class sidecar
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .expect("no skipped variants")
+            .get_name()
+            .to_owned()
+    }
+
+    /// Build the encoded message for a key of `k` bytes
+    fn encode<R: RngCore>(self, k: usize, n: &BigNumRef, rng: &mut R) -> Result<Vec<u8>> {
+        let em = match self {
+            ProbeClass::NoStructure => loop {
+                let mut em = vec![0; k];
+                rng.fill_bytes(&mut em);
+                if *BigNum::from_slice(&em)? < *n {
+                    break em;
+                }
+            },
+            ProbeClass::Valid0 => pkcs1_encode(k, 0, rng),
+            ProbeClass::Valid48 => pkcs1_encode(k, PROBE_MESSAGE_LEN, rng),
+            ProbeClass::ValidMax => pkcs1_encode(k, k - 3 - MIN_PADDING_LEN, rng),
+            ProbeClass::WrongFirstByte => {
+                let mut em = pkcs1_encode(k, PROBE_MESSAGE_LEN, rng);
+                em[0] = 0x01;
+                em
+            }
+            ProbeClass::WrongBlockType => {
+                let mut em = pkcs1_encode(k, PROBE_MESSAGE_LEN, rng);
+                em[1] = 0x01;
+                em
+            }
+            ProbeClass::NoSeparator => {
+                let mut em = vec![0x00, 0x02];
+                em.extend(nonzero_bytes(k - 2, rng));
+                em
+            }
+            ProbeClass::ZeroInPadding => {
+                let mut em = pkcs1_encode(k, PROBE_MESSAGE_LEN, rng);
+                em[2 + MIN_PADDING_LEN / 2] = 0x00;
+                em
+            }
+            ProbeClass::ShortPadding => pkcs1_encode(k, k - 3 - (MIN_PADDING_LEN - 1), rng),
+        };
+
+        Ok(em)
+    }
+}
+
+/// Generate `len` random non-zero bytes
+fn nonzero_bytes<R: RngCore>(len: usize, rng: &mut R) -> Vec<u8> {
+    (0..len).map(|_| rng.gen_range(1..=u8::MAX)).collect()
+}
+
+/// Encode a random message of `msg_len` bytes with PKCS#1 v1.5 type 2 padding
+fn pkcs1_encode<R: RngCore>(k: usize, msg_len: usize, rng: &mut R) -> Vec<u8> {
+    let mut em = vec![0x00, 0x02];
+    em.extend(nonzero_bytes(k - 3 - msg_len, rng));
+    em.push(0x00);
+
+    let mut msg = vec![0; msg_len];
+    rng.fill_bytes(&mut msg);
+    em.extend(msg);
+
+    em
+}
+
+/// Message of a PKCS#1 v1.5 type 2 encoded block, `None` if the padding is
+/// invalid
+fn pkcs1_decode(em: &[u8]) -> Option<&[u8]> {
+    if em.len() < 3 + MIN_PADDING_LEN || em[0] != 0x00 || em[1] != 0x02 {
+        return None;
+    }
+
+    let separator = em[2..].iter().position(|&b| b == 0x00)? + 2;
+    if separator < 2 + MIN_PADDING_LEN {
+        return None;
+    }
+
+    Some(&em[separator + 1..])
+}
+
+/// Load the public half of a PEM encoded RSA key
+fn load_public_key(path: &str) -> Result<Rsa<Public>> {
+    let pem = std::fs::read(path).context("Failed to read key file")?;
+
+    if let Ok(pkey) = PKey::private_key_from_pem(&pem) {
+        let rsa = pkey.rsa().context("The provided key is not an RSA key")?;
+        return Rsa::from_public_components(rsa.n().to_owned()?, rsa.e().to_owned()?)
+            .context("Failed to extract public key");
+    }
+
+    Rsa::public_key_from_pem(&pem)
+        .or_else(|_| Rsa::public_key_from_pem_pkcs1(&pem))
+        .context("Failed to parse RSA key from PEM file")
+}
+
+/// Generate the ciphertexts and the class label sidecar
+pub fn generate(args: &GenerateArgs) -> Result<()> {
+    let rsa = load_public_key(&args.key)?;
+
+    let k: usize = rsa
+        .size()
+        .try_into()
+        .context("Failed to convert module length to usize")?;
+
+    if k < 3 + MIN_PADDING_LEN + PROBE_MESSAGE_LEN {
+        bail!("The provided key is too small");
+    }
+
+    let classes = if args.classes.is_empty() {
+        ProbeClass::value_variants().to_vec()
+    } else {
+        args.classes.clone()
+    };
+
+    let mut rng = match args.seed {
+        Some(seed) => ChaCha20Rng::seed_from_u64(seed),
+        None => ChaCha20Rng::from_entropy(),
+    };
+
+    let mut output =
+        BufWriter::new(File::create(&args.output).context("Failed to create output file")?);
+    let mut labels =
+        BufWriter::new(File::create(&args.labels).context("Failed to create labels file")?);
+    let mut expected = match &args.expected {
+        Some(path) => Some(BufWriter::new(
+            File::create(path).context("Failed to create expected plaintext file")?,
+        )),
+        None => None,
+    };
+
+    println!("key length: {} bits ({} bytes)", k * 8, k);
+    println!("classes: {}", classes.len());
+
+    let mut order = classes.clone();
+    let mut ciphertext = vec![0; k];
+
+    // Every class appears once per tuple, in a random order
+    for _ in 0..args.count {
+        order.shuffle(&mut rng);
+
+        for class in &order {
+            let em = class.encode(k, rsa.n(), &mut rng)?;
+
+            rsa.public_encrypt(&em, &mut ciphertext, Padding::NONE)
+                .with_context(|| format!("Failed to encrypt {} probe", class.name()))?;
+
+            output
+                .write_all(&ciphertext)
+                .context("failed to write ciphertext")?;
+            writeln!(&mut labels, "{}", class.name()).context("failed to write label")?;
+
+            // Decided from the block itself, as a random one can be valid
+            if let Some(expected) = &mut expected {
+                match pkcs1_decode(&em) {
+                    Some(message) => writeln!(expected, "{}", hex::encode(message)),
+                    None => writeln!(expected, "-"),
+                }
+                .context("failed to write expected plaintext")?;
+            }
+        }
+    }
+
+    output.flush().context("failed to write ciphertext")?;
+    labels.flush().context("failed to write label")?;
+    if let Some(expected) = &mut expected {
+        expected
+            .flush()
+            .context("failed to write expected plaintext")?;
+    }
+
+    println!("ciphertexts: {}", args.count * classes.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Size of the test key, in bytes
+    const K: usize = 128;
+
+    fn encode(class: ProbeClass, seed: u64) -> Vec<u8> {
+        let n = BigNum::from_slice(&[0xff; K]).unwrap();
+        let em = class
+            .encode(K, &n, &mut ChaCha20Rng::seed_from_u64(seed))
+            .unwrap();
+        assert_eq!(em.len(), K, "{}", class.name());
+        em
+    }
+
+    /// Position of the first zero byte after the block type
+    fn separator(em: &[u8]) -> Option<usize> {
+        em[2..].iter().position(|&b| b == 0).map(|i| i + 2)
+    }
+
+    #[test]
+    fn valid_classes_round_trip() {
+        for seed in 0..20 {
+            for (class, len) in [
+                (ProbeClass::Valid0, 0),
+                (ProbeClass::Valid48, PROBE_MESSAGE_LEN),
+                (ProbeClass::ValidMax, K - 3 - MIN_PADDING_LEN),
+            ] {
+                let em = encode(class, seed);
+                assert_eq!(em[..2], [0x00, 0x02]);
+                assert_eq!(separator(&em), Some(K - 1 - len));
+
+                let message = pkcs1_decode(&em).unwrap();
+                assert_eq!(message.len(), len, "{}", class.name());
+                assert_eq!(message, &em[K - len..]);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_classes_are_rejected() {
+        for seed in 0..20 {
+            let em = encode(ProbeClass::WrongFirstByte, seed);
+            assert_eq!(em[..2], [0x01, 0x02]);
+            assert_eq!(pkcs1_decode(&em), None);
+
+            let em = encode(ProbeClass::WrongBlockType, seed);
+            assert_eq!(em[..2], [0x00, 0x01]);
+            assert_eq!(pkcs1_decode(&em), None);
+
+            let em = encode(ProbeClass::NoSeparator, seed);
+            assert_eq!(separator(&em), None);
+            assert_eq!(pkcs1_decode(&em), None);
+
+            let em = encode(ProbeClass::ZeroInPadding, seed);
+            assert_eq!(separator(&em), Some(6));
+            assert_eq!(pkcs1_decode(&em), None);
+
+            let em = encode(ProbeClass::ShortPadding, seed);
+            assert_eq!(separator(&em), Some(2 + MIN_PADDING_LEN - 1));
+            assert_eq!(pkcs1_decode(&em), None);
+        }
+    }
+
+    #[test]
+    fn no_structure_is_below_the_modulus() {
+        let n = BigNum::from_slice(&[0x80; K]).unwrap();
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+
+        for _ in 0..20 {
+            let em = ProbeClass::NoStructure.encode(K, &n, &mut rng).unwrap();
+            assert!(*BigNum::from_slice(&em).unwrap() < *n);
+        }
+    }
+
+    #[test]
+    fn decode_padding_length_limits() {
+        let mut em = vec![0x00, 0x02];
+        em.extend([0x55; MIN_PADDING_LEN]);
+        em.extend([0x00, 0xaa]);
+        assert_eq!(pkcs1_decode(&em), Some(&[0xaa][..]));
+
+        em.remove(2);
+        assert_eq!(pkcs1_decode(&em), None);
+        assert_eq!(pkcs1_decode(&[0x00, 0x02, 0x00]), None);
+    }
+}
@@ -0,0 +1,372 @@
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use openssl::{
+    bn::{BigNum, BigNumContext, BigNumRef},
+    pkey::PKey,
+    rsa::Rsa,
+};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use std::str::FromStr;
+
+/// Size of the random public exponents, the FIPS 186-4 upper bound
+const RANDOM_EXPONENT_BITS: usize = 256;
+
+/// Generate an RSA private key
+#[derive(Args, Debug)]
+pub struct GenkeyArgs {
+    /// Output key file (PEM, PKCS#8)
+    #[arg(short = 'o', long)]
+    output: String,
+
+    /// Modulus size in bits
+    #[arg(short = 'b', long, default_value_t = 2048,
+          value_parser = clap::value_parser!(u64).range(512..=16384))]
+    bits: u64,
+
+    /// Public exponent: a number, or `random` for a random odd 256 bit exponent
+    #[arg(short = 'e', long, default_value = "65537")]
+    exponent: Exponent,
+
+    /// Number of primes, more than 2 for multi-prime RSA
+    #[arg(short = 'p', long, default_value_t = 2,
+          value_parser = clap::value_parser!(u64).range(2..=5))]
+    primes: u64,
+
+    /// Seed for the random generator, for reproducible keys
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+/// Public exponent of a generated key
+#[derive(Clone, Copy, Debug)]
+pub enum Exponent {
+    Fixed(u64),
+    Random,
+}
+
+impl FromStr for Exponent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Exponent::Random),
+            _ => match s.parse::<u64>() {
+                Ok(e) if e >= 3 && e % 2 == 1 => Ok(Exponent::Fixed(e)),
+                _ => Err(format!(
+                    "expected an odd number from 3 or `random`, got `{s}`"
+                )),
+            },
+        }
+    }
+}
+
+/// Largest number of primes OpenSSL accepts for a modulus size
+fn max_primes(bits: usize) -> usize {
+    match bits {
+        ..1024 => 2,
+        1024..4096 => 3,
+        4096..8192 => 4,
+        _ => 5,
+    }
+}
+
+/// Random number of exactly `bits` bits, with the top two bits set and odd
+///
+/// Setting the top two bits makes the product of two such numbers exactly
+/// twice as long.
+fn random_odd(bits: usize, rng: &mut ChaCha20Rng) -> Result<BigNum> {
+    let mut bytes = vec![0; bits.div_ceil(8)];
+    rng.fill_bytes(&mut bytes);
+
+    let excess = bytes.len() * 8 - bits;
+    bytes[0] &= 0xff >> excess;
+
+    let mut n = BigNum::from_slice(&bytes)?;
+    n.set_bit(bits as i32 - 1)?;
+    n.set_bit(bits as i32 - 2)?;
+    n.set_bit(0)?;
+    Ok(n)
+}
+
+/// Random prime of `bits` bits with `p - 1` coprime to `e`
+fn random_prime(
+    bits: usize,
+    e: &BigNumRef,
+    rng: &mut ChaCha20Rng,
+    ctx: &mut BigNumContext,
+) -> Result<BigNum> {
+    let one = BigNum::from_u32(1)?;
+    let mut p_1 = BigNum::new()?;
+    let mut gcd = BigNum::new()?;
+
+    loop {
+        let p = random_odd(bits, rng)?;
+
+        p_1.checked_sub(&p, &one)?;
+        gcd.gcd(&p_1, e, ctx)?;
+        if gcd != one {
+            continue;
+        }
+
+        if p.is_prime_fasttest(0, ctx, true)? {
+            return Ok(p);
+        }
+    }
+}
+
+/// Append a DER length
+fn der_length(out: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+}
+
+/// Append a DER INTEGER holding a non-negative number
+fn der_integer(out: &mut Vec<u8>, n: &BigNumRef) {
+    let mut bytes = n.to_vec();
+    if bytes.first().is_none_or(|&b| b & 0x80 != 0) {
+        bytes.insert(0, 0);
+    }
+
+    out.push(0x02);
+    der_length(out, bytes.len());
+    out.extend_from_slice(&bytes);
+}
+
+/// Append a DER SEQUENCE with the given contents
+fn der_sequence(out: &mut Vec<u8>, contents: &[u8]) {
+    out.push(0x30);
+    der_length(out, contents.len());
+    out.extend_from_slice(contents);
+}
+
+/// Encode a PKCS#1 RSAPrivateKey, with OtherPrimeInfos for more than two
+/// primes (RFC 8017, appendix A.1.2)
+fn encode_private_key(
+    primes: &[BigNum],
+    e: &BigNumRef,
+    ctx: &mut BigNumContext,
+) -> Result<Vec<u8>> {
+    let one = BigNum::from_u32(1)?;
+
+    // Modulus and lambda(n), the lcm of all p - 1
+    let mut n = BigNum::from_u32(1)?;
+    let mut lambda = BigNum::from_u32(1)?;
+    for p in primes {
+        let mut p_1 = BigNum::new()?;
+        p_1.checked_sub(p, &one)?;
+
+        let mut product = BigNum::new()?;
+        product.checked_mul(&n, p, ctx)?;
+        n = product;
+
+        let mut gcd = BigNum::new()?;
+        gcd.gcd(&lambda, &p_1, ctx)?;
+        let mut product = BigNum::new()?;
+        product.checked_mul(&lambda, &p_1, ctx)?;
+        lambda.checked_div(&product, &gcd, ctx)?;
+    }
+
+    let mut d = BigNum::new()?;
+    d.mod_inverse(e, &lambda, ctx)
+        .context("The public exponent is not invertible")?;
+
+    // CRT exponent d mod (p - 1) of a prime
+    let exponent = |p: &BigNumRef, ctx: &mut BigNumContext| -> Result<BigNum> {
+        let mut p_1 = BigNum::new()?;
+        p_1.checked_sub(p, &one)?;
+        let mut exponent = BigNum::new()?;
+        exponent.nnmod(&d, &p_1, ctx)?;
+        Ok(exponent)
+    };
+
+    let version = BigNum::from_u32(if primes.len() > 2 { 1 } else { 0 })?;
+    let (p, q) = (&primes[0], &primes[1]);
+
+    let mut qinv = BigNum::new()?;
+    qinv.mod_inverse(q, p, ctx)?;
+
+    let mut contents = Vec::new();
+    der_integer(&mut contents, &version);
+    der_integer(&mut contents, &n);
+    der_integer(&mut contents, e);
+    der_integer(&mut contents, &d);
+    der_integer(&mut contents, p);
+    der_integer(&mut contents, q);
+    der_integer(&mut contents, &*exponent(p, ctx)?);
+    der_integer(&mut contents, &*exponent(q, ctx)?);
+    der_integer(&mut contents, &qinv);
+
+    if primes.len() > 2 {
+        let mut infos = Vec::new();
+        let mut product = BigNum::new()?;
+        product.checked_mul(p, q, ctx)?;
+
+        for r in &primes[2..] {
+            let mut coefficient = BigNum::new()?;
+            coefficient.mod_inverse(&product, r, ctx)?;
+
+            let mut info = Vec::new();
+            der_integer(&mut info, r);
+            der_integer(&mut info, &*exponent(r, ctx)?);
+            der_integer(&mut info, &coefficient);
+            der_sequence(&mut infos, &info);
+
+            let mut next = BigNum::new()?;
+            next.checked_mul(&product, r, ctx)?;
+            product = next;
+        }
+
+        der_sequence(&mut contents, &infos);
+    }
+
+    let mut der = Vec::new();
+    der_sequence(&mut der, &contents);
+    Ok(der)
+}
+
+/// Generate the key and write it as PEM
+pub fn genkey(args: &GenkeyArgs) -> Result<()> {
+    let bits = args.bits as usize;
+    let count = args.primes as usize;
+
+    if count > max_primes(bits) {
+        bail!(
+            "OpenSSL supports at most {} primes for {bits} bit keys",
+            max_primes(bits)
+        );
+    }
+
+    let mut rng = match args.seed {
+        Some(seed) => ChaCha20Rng::seed_from_u64(seed),
+        None => ChaCha20Rng::from_entropy(),
+    };
+    let mut ctx = BigNumContext::new()?;
+
+    let e = match args.exponent {
+        Exponent::Fixed(e) => BigNum::from_slice(&e.to_be_bytes())?,
+        Exponent::Random => random_odd(RANDOM_EXPONENT_BITS, &mut rng)?,
+    };
+
+    println!("key length: {bits} bits");
+    println!("public exponent: {}", e.to_dec_str()?);
+    println!("primes: {count}");
+
+    // Split the modulus size evenly, the first primes taking the remainder,
+    // and retry in the rare case the product comes out one bit short
+    let primes = loop {
+        let mut primes = Vec::with_capacity(count);
+        for i in 0..count {
+            let size = bits / count + usize::from(i < bits % count);
+            let p = random_prime(size, &e, &mut rng, &mut ctx)?;
+            if primes.contains(&p) {
+                break;
+            }
+            primes.push(p);
+        }
+        if primes.len() != count {
+            continue;
+        }
+
+        let mut n = BigNum::from_u32(1)?;
+        for p in &primes {
+            let mut product = BigNum::new()?;
+            product.checked_mul(&n, p, &mut ctx)?;
+            n = product;
+        }
+
+        if n.num_bits() as usize == bits {
+            break primes;
+        }
+    };
+
+    let der = encode_private_key(&primes, &e, &mut ctx)?;
+    let rsa = Rsa::private_key_from_der(&der).context("Failed to load generated key")?;
+    if !rsa.check_key().context("Failed to check generated key")? {
+        bail!("The generated key is inconsistent");
+    }
+
+    let pem = PKey::from_rsa(rsa)?
+        .private_key_to_pem_pkcs8()
+        .context("Failed to encode private key")?;
+    std::fs::write(&args.output, pem).context("Failed to write key file")?;
+
+    println!("key: {}", args.output);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bn(n: u32) -> BigNum {
+        BigNum::from_u32(n).unwrap()
+    }
+
+    #[test]
+    fn der_lengths() {
+        for (len, expected) in [
+            (0, &[0x00][..]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0x81, 0x80]),
+            (0x1234, &[0x82, 0x12, 0x34]),
+        ] {
+            let mut out = Vec::new();
+            der_length(&mut out, len);
+            assert_eq!(out, expected, "length {len}");
+        }
+    }
+
+    #[test]
+    fn der_integers() {
+        for (n, expected) in [
+            (0, &[0x02, 0x01, 0x00][..]),
+            (0x7f, &[0x02, 0x01, 0x7f]),
+            (0x80, &[0x02, 0x02, 0x00, 0x80]),
+            (0x0100, &[0x02, 0x02, 0x01, 0x00]),
+        ] {
+            let mut out = Vec::new();
+            der_integer(&mut out, &bn(n));
+            assert_eq!(out, expected, "integer {n}");
+        }
+    }
+
+    #[test]
+    fn two_prime_key() {
+        let mut ctx = BigNumContext::new().unwrap();
+        let der = encode_private_key(&[bn(61), bn(53)], &bn(17), &mut ctx).unwrap();
+
+        let rsa = Rsa::private_key_from_der(&der).unwrap();
+        assert_eq!(*rsa.n(), *bn(3233));
+        assert_eq!(*rsa.e(), *bn(17));
+        // 17^-1 mod lcm(60, 52)
+        assert_eq!(*rsa.d(), *bn(413));
+        assert_eq!(rsa.dmp1().unwrap(), &*bn(53));
+        assert_eq!(rsa.dmq1().unwrap(), &*bn(49));
+        assert_eq!(rsa.iqmp().unwrap(), &*bn(38));
+    }
+
+    #[test]
+    fn multi_prime_key() {
+        let mut ctx = BigNumContext::new().unwrap();
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let e = bn(65537);
+        let primes: Vec<BigNum> = [342, 341, 341]
+            .into_iter()
+            .map(|bits| random_prime(bits, &e, &mut rng, &mut ctx).unwrap())
+            .collect();
+
+        let der = encode_private_key(&primes, &e, &mut ctx).unwrap();
+        let rsa = Rsa::private_key_from_der(&der).unwrap();
+        assert!(rsa.check_key().unwrap());
+        // OpenSSL writes the OtherPrimeInfos back the same way
+        assert_eq!(rsa.private_key_to_der().unwrap(), der);
+    }
+}
@@ -0,0 +1,585 @@
+use crate::{
+    backend::Backend,
+    binary::{self, BinaryReader},
+    clock::{Clock, Unit},
+    interrupt,
+    measurements::Measurements,
+    outcome::Outcome,
+};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, Write},
+};
+
+/// Run metadata, written as `# name: value` header lines
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    pub fn push(&mut self, name: &str, value: impl Display) {
+        self.entries.push((name.to_owned(), value.to_string()));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Add all entries of `other`
+    pub fn append(&mut self, other: &Metadata) {
+        self.entries.extend_from_slice(&other.entries);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+}
+
+/// One timed decryption
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    /// Position of the ciphertext in the input
+    pub index: usize,
+    /// Decryption time, in the unit of the harness
+    pub duration: u64,
+    pub outcome: Outcome,
+    /// Length of the plaintext
+    pub len: usize,
+    /// Worker of a parallel run which took the sample, 0 otherwise
+    pub worker: usize,
+    /// Core the worker was pinned to
+    pub cpu: Option<usize>,
+}
+
+/// Destination of the samples of a run
+pub trait Sink {
+    /// Called once before the first sample
+    fn begin(&mut self, _metadata: &Metadata) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for every decryption, with the plaintext it produced
+    fn record(&mut self, sample: &Sample, plaintext: &[u8]) -> Result<()>;
+
+    /// Whether the run should stop after the samples recorded so far
+    fn done(&self) -> bool {
+        false
+    }
+
+    /// Called once after the last sample
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Write samples as `duration,outcome,index,length` lines, after the metadata
+/// header
+///
+/// Samples of parallel runs, whose metadata has a `workers` entry, get two
+/// more columns with the worker and its core, `-` for unpinned workers.
+pub struct CsvSink<W> {
+    writer: W,
+    header: bool,
+    workers: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        CsvSink {
+            writer,
+            header: true,
+            workers: false,
+        }
+    }
+
+    /// Only write samples, to append to the output of an earlier run
+    pub fn without_header(mut self) -> Self {
+        self.header = false;
+        self
+    }
+}
+
+impl<W: Write> Sink for CsvSink<W> {
+    fn begin(&mut self, metadata: &Metadata) -> Result<()> {
+        self.workers = metadata.get("workers").is_some();
+        if !self.header {
+            return Ok(());
+        }
+        for (name, value) in metadata.iter() {
+            writeln!(self.writer, "# {name}: {value}").context("failed to write output header")?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, sample: &Sample, _plaintext: &[u8]) -> Result<()> {
+        write!(
+            self.writer,
+            "{},{},{},{}",
+            sample.duration, sample.outcome, sample.index, sample.len
+        )
+        .context("failed to write duration")?;
+
+        if self.workers {
+            match sample.cpu {
+                Some(cpu) => write!(self.writer, ",{},{cpu}", sample.worker),
+                None => write!(self.writer, ",{},-", sample.worker),
+            }
+            .context("failed to write duration")?;
+        }
+
+        writeln!(self.writer).context("failed to write duration")
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush().context("failed to write duration")
+    }
+}
+
+/// Read back an output file written by a [`CsvSink`] or a
+/// [`BinarySink`](crate::binary::BinarySink): its metadata and samples
+pub fn read_output(path: &str) -> Result<(Metadata, Vec<Sample>)> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {path}"))?;
+
+    let mut magic = [0; 8];
+    let binary = file.read_exact(&mut magic).is_ok() && &magic == binary::MAGIC;
+    file.rewind()
+        .with_context(|| format!("Failed to read {path}"))?;
+
+    if binary {
+        let reader = BinaryReader::new(BufReader::new(file))?;
+        let metadata = reader.metadata().clone();
+        let samples = reader
+            .map(|record| record.map(|r| r.sample()))
+            .collect::<Result<_>>()
+            .with_context(|| format!("Failed to read {path}"))?;
+        return Ok((metadata, samples));
+    }
+
+    let mut metadata = Metadata::default();
+    let mut samples = Vec::new();
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read {path}"))?;
+        if let Some(header) = line.strip_prefix('#') {
+            if let Some((name, value)) = header.split_once(':') {
+                metadata.push(name.trim(), value.trim());
+            }
+            continue;
+        }
+
+        let invalid = || format!("Invalid sample in {path} on line {}", i + 1);
+        let fields: Vec<&str> = line.split(',').collect();
+        let (duration, outcome, index, len, worker, cpu) = match fields[..] {
+            [duration, outcome, index, len] => (duration, outcome, index, len, "0", "-"),
+            [duration, outcome, index, len, worker, cpu] => {
+                (duration, outcome, index, len, worker, cpu)
+            }
+            _ => bail!(invalid()),
+        };
+
+        samples.push(Sample {
+            index: index.parse().with_context(invalid)?,
+            duration: duration.parse().with_context(invalid)?,
+            outcome: outcome.parse().with_context(invalid)?,
+            len: len.parse().with_context(invalid)?,
+            worker: worker.parse().with_context(invalid)?,
+            cpu: match cpu {
+                "-" => None,
+                cpu => Some(cpu.parse().with_context(invalid)?),
+            },
+        });
+    }
+
+    Ok((metadata, samples))
+}
+
+/// Encoding of the plaintexts written by a [`PlaintextSink`]
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaintextFormat {
+    /// One hex encoded plaintext per line, `-` for failed decryptions
+    Hex,
+    /// Plaintexts back to back, their lengths are in the output file
+    Raw,
+}
+
+/// Write the plaintext of every sample
+pub struct PlaintextSink<W> {
+    writer: W,
+    format: PlaintextFormat,
+}
+
+impl<W: Write> PlaintextSink<W> {
+    pub fn new(writer: W, format: PlaintextFormat) -> Self {
+        PlaintextSink { writer, format }
+    }
+}
+
+impl<W: Write> Sink for PlaintextSink<W> {
+    fn record(&mut self, sample: &Sample, plaintext: &[u8]) -> Result<()> {
+        match (self.format, sample.outcome) {
+            (PlaintextFormat::Hex, Outcome::Ok) => {
+                writeln!(self.writer, "{}", hex::encode(plaintext))
+            }
+            (PlaintextFormat::Hex, _) => writeln!(self.writer, "-"),
+            (PlaintextFormat::Raw, _) => self.writer.write_all(plaintext),
+        }
+        .context("failed to write plaintext")
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush().context("failed to write plaintext")
+    }
+}
+
+/// Keep every sample in memory
+impl Sink for Vec<Sample> {
+    fn record(&mut self, sample: &Sample, _plaintext: &[u8]) -> Result<()> {
+        self.push(*sample);
+        Ok(())
+    }
+}
+
+impl Sink for Measurements {
+    fn record(&mut self, sample: &Sample, _plaintext: &[u8]) -> Result<()> {
+        self.push(sample.index, sample.duration)
+    }
+}
+
+/// Totals of a run
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Summary {
+    /// Recorded decryptions
+    pub decryptions: usize,
+    /// Recorded decryptions which failed
+    pub failures: usize,
+    /// Decryptions discarded at the start of the run
+    pub discarded: usize,
+    /// Whether a sink ended the run before the end of its input
+    pub stopped: bool,
+    /// Whether the run ended early on an interrupt, see [`crate::interrupt`]
+    pub interrupted: bool,
+}
+
+/// Samples kept in memory during a run, and only handed to the sinks
+/// between chunks of decryptions
+#[derive(Clone, Copy, Debug)]
+pub struct Buffering {
+    /// Number of samples measured between two flushes to the sinks
+    pub chunk: usize,
+    /// Bytes kept for the plaintext of every sample, 0 to drop them
+    pub plaintext_size: usize,
+}
+
+impl Buffering {
+    /// Size of the buffers in bytes
+    pub fn memory(&self) -> usize {
+        self.chunk * (std::mem::size_of::<Sample>() + self.plaintext_size)
+    }
+}
+
+/// Time the decryptions of a backend with a clock
+pub struct Harness {
+    backend: Box<dyn Backend>,
+    clock: Clock,
+    unit: Unit,
+    metadata: Metadata,
+    /// Number of samples to drop at the start of each run
+    discard_first: usize,
+    /// Worker and core stamped on the samples of parallel runs
+    worker: usize,
+    cpu: Option<usize>,
+    buffering: Option<Buffering>,
+    /// Plaintext buffer, holding the plaintext of the last decryption
+    plaintext: Vec<u8>,
+    plaintext_len: usize,
+}
+
+impl Harness {
+    pub fn new(backend: Box<dyn Backend>, clock: Clock) -> Self {
+        let mut metadata = Metadata::default();
+
+        // Helpers timing their own decryptions replace the harness clock
+        let unit = if backend.self_timed() {
+            metadata.push("clock", "helper");
+            metadata.push("unit", Unit::Nanoseconds);
+            metadata.push("clock-overhead", 0);
+            Unit::Nanoseconds
+        } else {
+            metadata.push("clock", clock.source());
+            metadata.push("unit", clock.unit());
+            metadata.push("clock-overhead", clock.overhead());
+            clock.unit()
+        };
+
+        Harness {
+            backend,
+            clock,
+            unit,
+            metadata,
+            discard_first: 0,
+            worker: 0,
+            cpu: None,
+            buffering: None,
+            plaintext: Vec::new(),
+            plaintext_len: 0,
+        }
+    }
+
+    /// Unit of the sample durations
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Metadata to add entries to before the run
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+
+    pub fn backend(&self) -> &dyn Backend {
+        self.backend.as_ref()
+    }
+
+    /// Drop the first `count` samples of each run instead of reporting them
+    pub fn set_discard_first(&mut self, count: usize) {
+        self.discard_first = count;
+    }
+
+    /// Tag the samples as taken by a worker of a parallel run, pinned to
+    /// `cpu`
+    pub fn set_worker(&mut self, worker: usize, cpu: Option<usize>) {
+        self.worker = worker;
+        self.cpu = cpu;
+    }
+
+    /// Buffer the samples of each run in memory, so that sinks only write
+    /// them out between chunks of decryptions
+    pub fn set_buffering(&mut self, buffering: Option<Buffering>) {
+        self.buffering = buffering;
+    }
+
+    /// Decrypt every ciphertext of `source` exactly like [`Harness::run`]
+    /// does, without recording anything, returning the number of decryptions
+    ///
+    /// This brings caches, branch predictors and lazily initialized library
+    /// state, like OpenSSL's blinding and Montgomery contexts, to the state
+    /// they are in during the measurements.
+    pub fn warm_up<I>(&mut self, source: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = Result<Vec<u8>>>,
+    {
+        let mut count = 0;
+
+        for ciphertext in source {
+            std::hint::black_box(self.measure(count, &ciphertext?)?);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Decrypt and time a single ciphertext, at `index` in the input
+    #[inline]
+    pub fn measure(&mut self, index: usize, ciphertext: &[u8]) -> Result<Sample> {
+        self.plaintext.resize(ciphertext.len(), 0);
+
+        let start = self.clock.start();
+        let mut decryption = self.backend.decrypt(ciphertext, &mut self.plaintext)?;
+        let duration = self.clock.stop().wrapping_sub(start);
+
+        self.backend
+            .complete(&mut decryption, &mut self.plaintext)?;
+
+        self.plaintext_len = decryption.len;
+
+        Ok(Sample {
+            index,
+            duration: decryption.duration.unwrap_or(duration),
+            outcome: decryption.outcome,
+            len: decryption.len,
+            worker: self.worker,
+            cpu: self.cpu,
+        })
+    }
+
+    /// Plaintext produced by the last [`Harness::measure`]
+    pub fn plaintext(&self) -> &[u8] {
+        &self.plaintext[..self.plaintext_len]
+    }
+
+    /// Decrypt and time every ciphertext of `source`, reporting each sample
+    /// to all `sinks`
+    pub fn run<I>(&mut self, source: I, sinks: &mut [&mut dyn Sink]) -> Result<Summary>
+    where
+        I: IntoIterator<Item = Result<Vec<u8>>>,
+    {
+        let source = source
+            .into_iter()
+            .enumerate()
+            .map(|(index, ciphertext)| ciphertext.map(|c| (index, c)));
+        self.run_indexed(source, sinks)
+    }
+
+    /// Decrypt and time ciphertexts given with their index in the input,
+    /// like those of a [`Schedule`](crate::schedule::Schedule)
+    pub fn run_indexed<I, C>(&mut self, source: I, sinks: &mut [&mut dyn Sink]) -> Result<Summary>
+    where
+        I: IntoIterator<Item = Result<(usize, C)>>,
+        C: AsRef<[u8]>,
+    {
+        for sink in sinks.iter_mut() {
+            sink.begin(&self.metadata)?;
+        }
+
+        let mut summary = Summary::default();
+
+        if let Some(buffering) = self.buffering {
+            self.run_buffered(source, sinks, buffering, &mut summary)?;
+        } else {
+            self.run_streaming(source, sinks, &mut summary)?;
+        }
+
+        for sink in sinks.iter_mut() {
+            sink.finish()?;
+        }
+
+        Ok(summary)
+    }
+
+    /// Report every sample to the sinks right after its decryption
+    fn run_streaming<I, C>(
+        &mut self,
+        source: I,
+        sinks: &mut [&mut dyn Sink],
+        summary: &mut Summary,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = Result<(usize, C)>>,
+        C: AsRef<[u8]>,
+    {
+        for item in source {
+            let (index, ciphertext) = item?;
+            let sample = self.measure(index, ciphertext.as_ref())?;
+
+            if summary.discarded < self.discard_first {
+                summary.discarded += 1;
+                continue;
+            }
+
+            summary.decryptions += 1;
+            if sample.outcome != Outcome::Ok {
+                summary.failures += 1;
+            }
+
+            for sink in sinks.iter_mut() {
+                sink.record(&sample, self.plaintext())?;
+            }
+
+            if sinks.iter().any(|s| s.done()) {
+                summary.stopped = true;
+                break;
+            }
+            if interrupt::requested() {
+                summary.interrupted = true;
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keep the samples in preallocated buffers, reporting them to the sinks
+    /// only when a chunk is full and after the last decryption
+    ///
+    /// Sinks can only stop the run between chunks.
+    fn run_buffered<I, C>(
+        &mut self,
+        source: I,
+        sinks: &mut [&mut dyn Sink],
+        buffering: Buffering,
+        summary: &mut Summary,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = Result<(usize, C)>>,
+        C: AsRef<[u8]>,
+    {
+        let chunk = buffering.chunk.max(1);
+        let stride = buffering.plaintext_size;
+
+        // Write every element now, so that the run takes no page faults
+        let blank = std::hint::black_box(Sample {
+            index: 0,
+            duration: 0,
+            outcome: Outcome::Ok,
+            len: 0,
+            worker: 0,
+            cpu: None,
+        });
+        let mut samples = Vec::with_capacity(chunk);
+        samples.resize(chunk, blank);
+        samples.clear();
+        let mut plaintexts = Vec::new();
+        plaintexts.resize(chunk * stride, std::hint::black_box(0u8));
+
+        let flush = |samples: &[Sample], plaintexts: &[u8], sinks: &mut [&mut dyn Sink]| {
+            for (i, sample) in samples.iter().enumerate() {
+                let plaintext = match stride {
+                    0 => &[][..],
+                    _ => &plaintexts[i * stride..][..sample.len.min(stride)],
+                };
+                for sink in sinks.iter_mut() {
+                    sink.record(sample, plaintext)?;
+                }
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+
+        for item in source {
+            let (index, ciphertext) = item?;
+            let sample = self.measure(index, ciphertext.as_ref())?;
+
+            if summary.discarded < self.discard_first {
+                summary.discarded += 1;
+                continue;
+            }
+
+            summary.decryptions += 1;
+            if sample.outcome != Outcome::Ok {
+                summary.failures += 1;
+            }
+
+            if stride > 0 {
+                let plaintext = self.plaintext();
+                let len = plaintext.len().min(stride);
+                plaintexts[samples.len() * stride..][..len].copy_from_slice(&plaintext[..len]);
+            }
+            samples.push(sample);
+
+            if samples.len() == chunk {
+                flush(&samples, &plaintexts, sinks)?;
+                samples.clear();
+
+                if sinks.iter().any(|s| s.done()) {
+                    summary.stopped = true;
+                    break;
+                }
+            }
+            if interrupt::requested() {
+                summary.interrupted = true;
+                break;
+            }
+        }
+
+        flush(&samples, &plaintexts, sinks)
+    }
+}
@@ -0,0 +1,53 @@
+//! Graceful handling of SIGINT and SIGTERM
+//!
+//! Once [`install`]ed, the first signal only sets a flag which the
+//! [`Harness`](crate::harness::Harness) checks after every sample, so that
+//! the run ends cleanly and can be resumed. A second signal terminates the
+//! process as usual.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether an interrupt was received since the handler was installed
+#[inline]
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+extern "C" fn handle(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Install the handler for SIGINT and SIGTERM
+#[cfg(unix)]
+pub fn install() -> anyhow::Result<()> {
+    use anyhow::bail;
+
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic, which is async-signal
+        // safe, and SA_RESETHAND restores the default action after it ran
+        let res = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESETHAND;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if res != 0 {
+            bail!(
+                "Failed to install signal handler: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Interrupts end the process right away on other platforms
+#[cfg(not(unix))]
+pub fn install() -> anyhow::Result<()> {
+    Ok(())
+}
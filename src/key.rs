@@ -0,0 +1,181 @@
+use crate::backend::{self, Backend, BackendKind, Config};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use openssl::{
+    pkcs12::Pkcs12,
+    pkey::{Id, PKey, Private},
+    rsa::Rsa,
+};
+
+/// Encoding of a private key file
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyFormat {
+    /// Detect the format from the file contents
+    Auto,
+    /// PEM, PKCS#1 or PKCS#8, optionally encrypted
+    Pem,
+    /// DER, PKCS#1 or PKCS#8, optionally encrypted
+    Der,
+    /// PKCS#12 bundle
+    Pkcs12,
+}
+
+/// Read a passphrase from a `file:PATH`, `env:VAR` or `fd:N` source
+///
+/// Only the first line of a file or descriptor is used, like OpenSSL's
+/// `-passin` does. Passphrases are never taken from the command line, where
+/// other users could see them.
+pub fn read_passphrase(source: &str) -> Result<Vec<u8>> {
+    let (kind, value) = source
+        .split_once(':')
+        .context("Passphrase source must be file:PATH, env:VAR or fd:N")?;
+
+    let data = match kind {
+        "file" => std::fs::read(value).context("Failed to read passphrase file")?,
+        "env" => std::env::var(value)
+            .with_context(|| format!("Failed to read passphrase from ${value}"))?
+            .into_bytes(),
+        #[cfg(unix)]
+        "fd" => {
+            use std::{io::Read, mem::ManuallyDrop, os::fd::FromRawFd};
+
+            let fd: i32 = value.parse().context("Invalid passphrase descriptor")?;
+            // SAFETY: F_GETFD only reads the descriptor flags, and fails on
+            // descriptors which are not open
+            if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                bail!("Passphrase descriptor {value} is not open");
+            }
+
+            // SAFETY: the descriptor is open, and the file is never dropped,
+            // so the descriptor stays owned by whoever opened it
+            let mut file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)
+                .context("Failed to read passphrase descriptor")?;
+            data
+        }
+        _ => bail!("Unknown passphrase source {kind}, expected file, env or fd"),
+    };
+
+    let passphrase = match kind {
+        "env" => data,
+        _ => {
+            let line = data.split(|&b| b == b'\n').next().unwrap_or_default();
+            line.strip_suffix(b"\r").unwrap_or(line).to_vec()
+        }
+    };
+
+    Ok(passphrase)
+}
+
+/// Parse a PEM private key, PKCS#1 or PKCS#8, encrypted or not
+fn from_pem(data: &[u8], passphrase: Option<&[u8]>) -> Result<PKey<Private>> {
+    match passphrase {
+        Some(passphrase) => PKey::private_key_from_pem_passphrase(data, passphrase)
+            .context("Failed to parse private key from PEM file (wrong passphrase?)"),
+        // Fail on encrypted keys instead of prompting on the terminal
+        None => PKey::private_key_from_pem_callback(data, |_| Ok(0))
+            .context("Failed to parse private key from PEM file (encrypted keys need --key-pass)"),
+    }
+}
+
+/// Parse a DER private key, PKCS#1 or PKCS#8, encrypted or not
+fn from_der(data: &[u8], passphrase: Option<&[u8]>) -> Result<PKey<Private>> {
+    if let Some(passphrase) = passphrase {
+        if let Ok(pkey) = PKey::private_key_from_pkcs8_passphrase(data, passphrase) {
+            return Ok(pkey);
+        }
+    }
+
+    if let Ok(pkey) = PKey::private_key_from_der(data) {
+        return Ok(pkey);
+    }
+
+    let rsa =
+        Rsa::private_key_from_der(data).context("Failed to parse private key from DER file")?;
+    PKey::from_rsa(rsa).context("Failed to parse private key from DER file")
+}
+
+/// Extract the private key of a PKCS#12 bundle
+fn from_pkcs12(data: &[u8], passphrase: Option<&[u8]>) -> Result<PKey<Private>> {
+    let passphrase = match passphrase {
+        Some(passphrase) => {
+            std::str::from_utf8(passphrase).context("PKCS#12 passphrase is not UTF-8")?
+        }
+        None => "",
+    };
+
+    let bundle = Pkcs12::from_der(data).context("Failed to parse PKCS#12 file")?;
+    let parsed = bundle
+        .parse2(passphrase)
+        .context("Failed to decrypt PKCS#12 file (wrong passphrase, or legacy algorithms?)")?;
+    parsed.pkey.context("The PKCS#12 file holds no private key")
+}
+
+/// RSA private key to measure decryptions with
+pub struct PrivateKey {
+    pkey: PKey<Private>,
+    /// Modulus size in bytes
+    size: usize,
+}
+
+impl PrivateKey {
+    /// Load a private key file in the given format
+    pub fn from_file(path: &str, format: KeyFormat, passphrase: Option<&[u8]>) -> Result<Self> {
+        let data = std::fs::read(path).context("Failed to read key file")?;
+
+        let format = match format {
+            KeyFormat::Auto if data.trim_ascii_start().starts_with(b"-----BEGIN") => KeyFormat::Pem,
+            KeyFormat::Auto if Pkcs12::from_der(&data).is_ok() => KeyFormat::Pkcs12,
+            KeyFormat::Auto => KeyFormat::Der,
+            format => format,
+        };
+
+        let pkey = match format {
+            KeyFormat::Pem => from_pem(&data, passphrase)?,
+            KeyFormat::Der => from_der(&data, passphrase)?,
+            KeyFormat::Pkcs12 => from_pkcs12(&data, passphrase)?,
+            KeyFormat::Auto => unreachable!(),
+        };
+
+        Self::from_pkey(pkey)
+    }
+
+    pub fn from_pkey(pkey: PKey<Private>) -> Result<Self> {
+        if pkey.id() != Id::RSA {
+            bail!("The provided key is not an RSA key");
+        }
+
+        let size = pkey
+            .rsa()
+            .context("Failed getting RSA key from PKey")?
+            .size()
+            .try_into()
+            .context("Failed to convert module lenght to usize")?;
+
+        Ok(PrivateKey { pkey, size })
+    }
+
+    pub fn pkey(&self) -> &PKey<Private> {
+        &self.pkey
+    }
+
+    /// Modulus size in bytes, which is also the ciphertext size
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// SHA-256 of the DER encoded public key, hex encoded
+    pub fn fingerprint(&self) -> Result<String> {
+        let der = self
+            .pkey
+            .public_key_to_der()
+            .context("Failed to encode public key")?;
+        Ok(hex::encode(openssl::sha::sha256(&der)))
+    }
+
+    /// Load the key into the selected library, configured for decryption
+    pub fn decrypter(&self, kind: BackendKind, config: &Config) -> Result<Box<dyn Backend>> {
+        backend::new_backend(kind, &self.pkey, config)
+    }
+}
@@ -0,0 +1,24 @@
+//! RSA decryption timing harness
+//!
+//! Load a key with [`key::PrivateKey`], configure one of the [`backend`]s
+//! for it, and feed a [`harness::Harness`] with ciphertexts from a
+//! [`source::CiphertextSource`] or any other iterator. Every decryption is
+//! reported to the [`harness::Sink`]s given to [`harness::Harness::run`].
+
+pub mod backend;
+pub mod binary;
+pub mod checkpoint;
+pub mod clock;
+pub mod dudect;
+pub mod environment;
+pub mod harness;
+pub mod interrupt;
+pub mod key;
+pub mod measurements;
+pub mod outcome;
+pub mod parallel;
+pub mod schedule;
+pub mod source;
+pub mod stats;
+pub mod tuning;
+pub mod verify;
@@ -0,0 +1,1234 @@
+use anyhow::{bail, Context, Result};
+use clap::{ArgAction, Args, ValueEnum};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rsa_decrypt_timing::{
+    backend::{BackendKind, Config, Digest, ImplicitRejection, PaddingMode},
+    binary::BinarySink,
+    checkpoint::{self, Checkpoint},
+    clock::{Clock, ClockSource, Unit},
+    dudect::{self, Dudect},
+    environment,
+    harness::{
+        self, Buffering, CsvSink, Harness, Metadata, PlaintextFormat, PlaintextSink, Sample, Sink,
+        Summary,
+    },
+    interrupt,
+    key::{self, KeyFormat, PrivateKey},
+    measurements::{self, Layout, Measurements},
+    outcome::Outcome,
+    parallel::Workers,
+    schedule::Schedule,
+    source::CiphertextSource,
+    tuning::{self, Tuning},
+    verify::{self, Verifier},
+};
+use serde_json::{json, Map, Value};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    str,
+};
+
+/// Measure decryption timing of a ciphertext file
+#[derive(Args, Debug)]
+pub struct MeasureArgs {
+    /// Key file
+    #[arg(short = 'k', long)]
+    key: String,
+
+    /// Key file format
+    #[arg(long, value_enum, default_value_t = KeyFormat::Auto)]
+    key_format: KeyFormat,
+
+    /// Source of the key passphrase: file:PATH, env:VAR or fd:N
+    #[arg(long)]
+    key_pass: Option<String>,
+
+    /// Input file
+    #[arg(short = 'i', long)]
+    input: String,
+
+    /// Output file
+    #[arg(short = 'o', long)]
+    output: String,
+
+    /// Format of the output file
+    #[arg(short = 'f', long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Debug option to print the decrypted data to stdout
+    #[arg(short = 's', long, action=ArgAction::SetTrue)]
+    stdout: Option<bool>,
+
+    #[command(flatten)]
+    decrypt: DecryptArgs,
+
+    /// Class label file matching the input, one label per ciphertext
+    #[arg(short = 'l', long)]
+    labels: Option<String>,
+
+    /// Output file for the class-labelled measurements
+    #[arg(short = 'm', long, requires = "labels")]
+    measurements: Option<String>,
+
+    /// Layout of the measurements file
+    #[arg(long, value_enum, default_value_t = Layout::Wide)]
+    layout: Layout,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Number of worker threads measuring in parallel, each with its own
+    /// backend and share of the schedule
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..),
+          conflicts_with_all = ["cpu", "resume", "online", "flush_every"])]
+    threads: Option<u64>,
+
+    /// Cores to pin the workers to, one worker per core, e.g. `2-5,8`
+    #[arg(long, conflicts_with_all = ["cpu", "resume", "online", "flush_every"])]
+    cpus: Option<String>,
+
+    /// Number of times each ciphertext is decrypted
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    repeat: u64,
+
+    /// Decrypt the ciphertexts in a random order, in tuples of one
+    /// ciphertext per class when labels are given
+    #[arg(long)]
+    shuffle: bool,
+
+    /// Seed for the shuffled order, for reproducible schedules (implies --shuffle)
+    #[arg(long)]
+    shuffle_seed: Option<u64>,
+
+    /// Read all ciphertexts into memory and keep the samples in a
+    /// preallocated buffer, writing them out only after the run, as the
+    /// workers of parallel runs always do
+    #[arg(long)]
+    buffered: bool,
+
+    /// Write buffered samples out every N decryptions instead of after the
+    /// run (implies --buffered)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    flush_every: Option<u64>,
+
+    /// Run dudect-style t-tests between the classes while measuring, the
+    /// outcomes being the classes when no labels are given, and stop once a
+    /// leak is detected
+    #[arg(long)]
+    online: bool,
+
+    /// |t| above which the online test reports a leak and stops the run
+    #[arg(long, requires = "online", default_value_t = dudect::DEFAULT_THRESHOLD)]
+    leak_threshold: f64,
+
+    /// Stop the online test after this many recorded decryptions
+    #[arg(long, requires = "online")]
+    max_samples: Option<usize>,
+
+    /// Continue an interrupted run from its checkpoint, appending to its
+    /// output; the other options must be the same as for the first run
+    #[arg(long)]
+    resume: bool,
+
+    /// Expected plaintext file to verify the decryptions against, one hex
+    /// plaintext or `-` for an expected failure per ciphertext
+    #[arg(short = 'e', long)]
+    expected: Option<String>,
+
+    /// Output file for the decrypted plaintexts
+    #[arg(long)]
+    plaintext_out: Option<String>,
+
+    /// Encoding of the plaintext output file
+    #[arg(long, value_enum, default_value_t = PlaintextFormat::Hex)]
+    plaintext_format: PlaintextFormat,
+}
+
+/// Decryption options shared by the measure and sweep subcommands
+#[derive(Args, Debug)]
+pub struct DecryptArgs {
+    /// Padding scheme
+    #[arg(short = 'p', long, value_enum, default_value_t = PaddingMode::Pkcs1)]
+    pub padding: PaddingMode,
+
+    /// OAEP digest
+    #[arg(long, value_enum, default_value_t = Digest::Sha1)]
+    pub oaep_md: Digest,
+
+    /// MGF1 digest used by OAEP (defaults to the OAEP digest)
+    #[arg(long, value_enum)]
+    pub mgf1_md: Option<Digest>,
+
+    /// OAEP label, hex encoded
+    #[arg(long)]
+    pub oaep_label: Option<String>,
+
+    /// Implicit rejection for PKCS1 padding (requires OpenSSL 3.2+ to take effect)
+    #[arg(long, value_enum, default_value_t = ImplicitRejection::Default)]
+    pub implicit_rejection: ImplicitRejection,
+
+    /// Clock used to time the decryptions
+    #[arg(short = 'c', long, value_enum, default_value_t = ClockSource::Instant)]
+    pub clock: ClockSource,
+
+    /// Library performing the decryptions
+    #[arg(short = 'b', long, value_enum, default_value_t = BackendKind::Openssl)]
+    pub backend: BackendKind,
+
+    /// Helper program run by the external backend
+    #[arg(long, required_if_eq("backend", "external"))]
+    pub helper: Option<String>,
+
+    /// Argument passed to the helper program, may be repeated
+    #[arg(long, requires = "helper", allow_hyphen_values = true)]
+    pub helper_arg: Vec<String>,
+}
+
+impl DecryptArgs {
+    /// Backend configuration for these options
+    pub fn config(&self) -> Result<Config> {
+        Ok(Config {
+            padding: self.padding,
+            oaep_md: self.oaep_md,
+            mgf1_md: self.mgf1_md.unwrap_or(self.oaep_md),
+            oaep_label: match &self.oaep_label {
+                Some(label) => Some(hex::decode(label).context("Failed to decode OAEP label")?),
+                None => None,
+            },
+            implicit_rejection: self.implicit_rejection,
+            helper: self
+                .helper
+                .iter()
+                .chain(&self.helper_arg)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+/// Setup of the measuring thread and of the start of the run, shared by the
+/// measure and sweep subcommands
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Pin the measuring thread to this CPU
+    #[arg(long)]
+    pub cpu: Option<usize>,
+
+    /// Run the measuring thread with SCHED_FIFO at this priority
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..=99))]
+    pub fifo_priority: Option<i32>,
+
+    /// Lock all memory pages to avoid page faults while measuring
+    #[arg(long)]
+    pub mlock: bool,
+
+    /// Decryptions to run before recording, cycling through the input, or
+    /// `input` for one pass over the whole input
+    #[arg(long, value_parser = parse_warmup)]
+    pub warmup: Option<Warmup>,
+
+    /// Number of recorded decryptions to drop at the start of the run
+    #[arg(long, default_value_t = 0)]
+    pub discard_first: usize,
+}
+
+impl RunArgs {
+    /// Scheduling settings of the measuring thread
+    pub fn tuning(&self) -> Tuning {
+        Tuning {
+            cpu: self.cpu,
+            fifo_priority: self.fifo_priority,
+            mlock: self.mlock,
+        }
+    }
+}
+
+/// Format of the measure output file
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// `# name: value` header lines, then `duration,outcome,index,length` lines
+    Text,
+    /// Fixed size binary records, see the `convert` subcommand
+    Binary,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", value_name(*self))
+    }
+}
+
+/// Length of the warm-up phase
+#[derive(Clone, Copy, Debug)]
+pub enum Warmup {
+    Decryptions(usize),
+    Input,
+}
+
+fn parse_warmup(s: &str) -> Result<Warmup, String> {
+    match s {
+        "input" => Ok(Warmup::Input),
+        _ => s
+            .parse()
+            .map(Warmup::Decryptions)
+            .map_err(|_| format!("expected a number of decryptions or `input`, got `{s}`")),
+    }
+}
+
+/// Run the warm-up decryptions, returning their number
+fn warm_up(harness: &mut Harness, warmup: Warmup, input: &str, len: usize) -> Result<usize> {
+    match warmup {
+        Warmup::Input => harness.warm_up(CiphertextSource::open(input, len)?),
+        Warmup::Decryptions(count) => {
+            let mut done = 0;
+
+            while done < count {
+                let source = CiphertextSource::open(input, len)?.take(count - done);
+                match harness.warm_up(source)? {
+                    0 => bail!("Failed to read input file: too small"),
+                    n => done += n,
+                }
+            }
+
+            Ok(done)
+        }
+    }
+}
+
+fn print_stdout(data: &[u8]) {
+    let r = str::from_utf8(data);
+
+    match r {
+        Ok(s) => {
+            println!("{s}");
+        }
+        Err(_) => {
+            for b in data {
+                print!("{b:02X?}");
+            }
+            println!();
+        }
+    }
+}
+
+/// Console output: decrypted data on request, and progress
+struct Console {
+    stdout: bool,
+    /// Print the iteration count, unless the online test reports progress
+    progress: bool,
+    count: usize,
+}
+
+impl Sink for Console {
+    fn record(&mut self, sample: &Sample, plaintext: &[u8]) -> Result<()> {
+        if self.stdout && sample.outcome == Outcome::Ok {
+            print_stdout(plaintext);
+        }
+
+        self.count += 1;
+        if self.progress && self.count.is_multiple_of(10000) {
+            println!("iteration {}", self.count);
+        }
+
+        Ok(())
+    }
+}
+
+/// Write the class-labelled measurements in a tlsfuzzer layout
+pub(crate) fn write_measurements(
+    measurements: &Measurements,
+    path: &str,
+    layout: Layout,
+    unit: Unit,
+) -> Result<()> {
+    let tuples = measurements.write(path, layout, unit)?;
+    if tuples * measurements.class_count() < measurements.len() {
+        println!("warning: dropping incomplete last tuple");
+    }
+    println!("measurements: {tuples} tuples");
+
+    Ok(())
+}
+
+/// Online test, printing its largest |t| as progress
+struct Online(Dudect);
+
+impl Sink for Online {
+    fn begin(&mut self, metadata: &Metadata) -> Result<()> {
+        self.0.begin(metadata)
+    }
+
+    fn record(&mut self, sample: &Sample, plaintext: &[u8]) -> Result<()> {
+        let dudect = &mut self.0;
+        dudect.record(sample, plaintext)?;
+
+        if dudect.count().is_multiple_of(dudect::REPORT_EVERY) {
+            println!(
+                "samples {}: max |t| {}",
+                dudect.count(),
+                dudect.describe(&dudect.max_t())
+            );
+        }
+
+        Ok(())
+    }
+
+    fn done(&self) -> bool {
+        self.0.done()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.0.finish()
+    }
+}
+
+/// Print the effective scheduling settings, and warnings about the core
+pub(crate) fn print_tuning(tuning: &Metadata) {
+    for (name, value) in tuning.iter() {
+        println!("{name}: {value}");
+    }
+    for warning in tuning::warnings(tuning) {
+        println!("warning: {warning}");
+    }
+}
+
+/// Cores a thread may run on, from its tuning metadata
+pub(crate) fn affinity(tuning: &Metadata) -> Vec<usize> {
+    tuning
+        .get("affinity")
+        .and_then(|list| tuning::parse_cpu_list(list).ok())
+        .unwrap_or_default()
+}
+
+/// Command line name of an option value
+fn value_name(value: impl ValueEnum) -> String {
+    let value = value.to_possible_value().expect("no skipped variants");
+    value.get_name().to_owned()
+}
+
+/// Options a resumed run must share with the run it continues, by name
+///
+/// Files are identified by their SHA-256 hash.
+fn resume_settings(args: &MeasureArgs) -> Result<Vec<(String, String)>> {
+    let decrypt = &args.decrypt;
+    let helper: Vec<&String> = decrypt.helper.iter().chain(&decrypt.helper_arg).collect();
+    let expected = match &args.expected {
+        Some(path) => checkpoint::file_sha256(path)?,
+        None => "-".to_owned(),
+    };
+
+    let settings = [
+        ("padding", value_name(decrypt.padding)),
+        ("oaep-md", value_name(decrypt.oaep_md)),
+        (
+            "mgf1-md",
+            value_name(decrypt.mgf1_md.unwrap_or(decrypt.oaep_md)),
+        ),
+        (
+            "oaep-label",
+            decrypt.oaep_label.clone().unwrap_or("-".to_owned()),
+        ),
+        ("implicit-rejection", value_name(decrypt.implicit_rejection)),
+        ("backend", value_name(decrypt.backend)),
+        ("helper", format!("{helper:?}")),
+        ("clock", value_name(decrypt.clock)),
+        ("discard-first", args.run.discard_first.to_string()),
+        ("expected", expected),
+        ("plaintext-format", value_name(args.plaintext_format)),
+    ];
+
+    Ok(settings
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect())
+}
+
+/// Format a byte count in MiB
+fn mebibytes(bytes: usize) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// Create an output file, or cut it back to its length at the checkpoint of
+/// a resumed run to append to it
+fn open_output(path: &str, resume_len: Option<u64>) -> Result<File> {
+    match resume_len {
+        Some(len) => {
+            let file = OpenOptions::new()
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open {path}"))?;
+            file.set_len(len)
+                .with_context(|| format!("Failed to truncate {path}"))?;
+            Ok(file)
+        }
+        None => File::create(path).with_context(|| format!("Failed to create {path}")),
+    }
+}
+
+/// Remove the checkpoint of an output file, so that a new or finished output
+/// is never cut back to it
+fn remove_checkpoint(output: &str) -> Result<()> {
+    match fs::remove_file(checkpoint::path(output)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).context("Failed to remove checkpoint file")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Implicit rejection state of a backend, as printed and recorded
+pub(crate) fn implicit_rejection_mode(implicit_rejection: Option<bool>) -> &'static str {
+    match implicit_rejection {
+        Some(true) => "enabled",
+        Some(false) => "disabled",
+        None => "unsupported",
+    }
+}
+
+/// Run description shared by the measure and sweep subcommands, extended
+/// with the `fields` of the run
+pub(crate) fn sidecar(
+    started: &str,
+    cpus: &[usize],
+    decrypt: &DecryptArgs,
+    metadata: &Metadata,
+    fields: Value,
+) -> Value {
+    let metadata: Map<String, Value> = metadata
+        .iter()
+        .map(|(name, value)| (name.to_owned(), Value::from(value)))
+        .collect();
+
+    let mut sidecar = json!({
+        "tool": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "args": std::env::args().collect::<Vec<_>>(),
+        "started": started,
+        "finished": environment::timestamp(),
+        "openssl": environment::openssl(),
+        "system": environment::system(cpus),
+        "backend": format!("{:?}", decrypt.backend),
+        "padding": format!("{:?}", decrypt.padding),
+        "metadata": metadata,
+    });
+    if let (Value::Object(sidecar), Value::Object(fields)) = (&mut sidecar, fields) {
+        sidecar.extend(fields);
+    }
+
+    sidecar
+}
+
+/// Write the run description next to the output file
+pub(crate) fn write_sidecar(path: &str, sidecar: &Value) -> Result<()> {
+    let file = File::create(path).context("Failed to create metadata file")?;
+    let mut writer = BufWriter::new(file);
+
+    serde_json::to_writer_pretty(&mut writer, sidecar).context("failed to write metadata")?;
+    writeln!(writer).context("failed to write metadata")?;
+    writer.flush().context("failed to write metadata")?;
+
+    println!("metadata: {path}");
+
+    Ok(())
+}
+
+/// Read the checkpoint of the run continued with `args`, and check that
+/// the run is the same
+fn resume(args: &MeasureArgs) -> Result<Checkpoint> {
+    let checkpoint = Checkpoint::read(&checkpoint::path(&args.output))?;
+
+    Checkpoint::verify("key", &args.key, &checkpoint.key_sha256)?;
+    Checkpoint::verify("input", &args.input, &checkpoint.input_sha256)?;
+    match (&args.labels, &checkpoint.labels_sha256) {
+        (Some(labels), Some(hash)) => Checkpoint::verify("labels", labels, hash)?,
+        (None, None) => (),
+        _ => bail!("The run used labels differently, it cannot be resumed"),
+    }
+    if checkpoint.format != args.format.to_string() {
+        bail!(
+            "The run wrote {} output, it cannot be resumed",
+            checkpoint.format
+        );
+    }
+    if checkpoint.repeat != args.repeat {
+        bail!(
+            "The run used --repeat {}, it cannot be resumed",
+            checkpoint.repeat
+        );
+    }
+    if args.plaintext_out.is_some() != checkpoint.plaintext_len.is_some() {
+        bail!("The run used --plaintext-out differently, it cannot be resumed");
+    }
+    checkpoint.verify_settings(&resume_settings(args)?)?;
+
+    println!(
+        "resuming: {} samples, {} decryptions done",
+        checkpoint.samples, checkpoint.position
+    );
+    Ok(checkpoint)
+}
+
+/// Write the checkpoint of an interrupted run
+fn save_checkpoint(
+    args: &MeasureArgs,
+    resumed: Option<Checkpoint>,
+    shuffle_seed: Option<u64>,
+    summary: &Summary,
+    verifier: Option<&Verifier>,
+) -> Result<Checkpoint> {
+    let previous = resumed.unwrap_or_default();
+    let checkpoint = Checkpoint {
+        key_sha256: checkpoint::file_sha256(&args.key)?,
+        input_sha256: checkpoint::file_sha256(&args.input)?,
+        labels_sha256: args
+            .labels
+            .as_deref()
+            .map(checkpoint::file_sha256)
+            .transpose()?,
+        format: args.format.to_string(),
+        repeat: args.repeat,
+        shuffle_seed,
+        position: previous.position + summary.decryptions + summary.discarded,
+        samples: previous.samples + summary.decryptions,
+        discarded: previous.discarded + summary.discarded,
+        output_len: fs::metadata(&args.output)?.len(),
+        plaintext_len: match &args.plaintext_out {
+            Some(path) => Some(fs::metadata(path)?.len()),
+            None => None,
+        },
+        settings: resume_settings(args)?,
+        verifier: verifier.map(Verifier::state),
+    };
+    let path = checkpoint::path(&args.output);
+    checkpoint.write(&path)?;
+
+    println!("checkpoint: {path}");
+    Ok(checkpoint)
+}
+
+/// Cores of the workers of a parallel run, `None` for unpinned workers
+fn worker_cpus(args: &MeasureArgs) -> Result<Option<Vec<Option<usize>>>> {
+    Ok(match (args.threads, &args.cpus) {
+        (threads, Some(list)) => {
+            let cpus = tuning::parse_cpu_list(list)?;
+            if cpus.is_empty() {
+                bail!("No cores in --cpus {list}");
+            }
+            if threads.is_some_and(|t| t as usize != cpus.len()) {
+                bail!("--threads must match the {} cores of --cpus", cpus.len());
+            }
+            Some(cpus.into_iter().map(Some).collect())
+        }
+        (Some(threads), None) => Some(vec![None; threads as usize]),
+        (None, None) => None,
+    })
+}
+
+/// Seed of the shuffled order, which a resumed run takes from its
+/// checkpoint
+fn shuffle_seed(args: &MeasureArgs, resumed: Option<&Checkpoint>) -> Result<Option<u64>> {
+    Ok(match (args.shuffle, args.shuffle_seed, resumed) {
+        (_, Some(seed), Some(c)) if c.shuffle_seed != Some(seed) => {
+            bail!("The run used a different shuffle seed, it cannot be resumed")
+        }
+        (_, _, Some(c)) => c.shuffle_seed,
+        (_, Some(seed), None) => Some(seed),
+        (true, None, None) => Some(rand::random()),
+        (false, None, None) => None,
+    })
+}
+
+/// Read all ciphertexts, and the order to decrypt them in
+///
+/// Shuffled runs with labels keep whole tuples of classes together.
+fn schedule(
+    source: impl Iterator<Item = Result<Vec<u8>>>,
+    repeat: usize,
+    shuffle_seed: Option<u64>,
+    measurements: Option<&Measurements>,
+) -> Result<(Vec<Vec<u8>>, Schedule)> {
+    let ciphertexts = source.collect::<Result<Vec<_>>>()?;
+
+    let schedule = match shuffle_seed {
+        Some(seed) => {
+            let mut rng = ChaCha20Rng::seed_from_u64(seed);
+            match measurements {
+                Some(measurements) => {
+                    let labels = measurements.labels();
+                    let labels = &labels[..labels.len().min(ciphertexts.len())];
+                    let schedule = Schedule::shuffled_tuples(labels, repeat, &mut rng)?;
+                    let left_out = labels.len() - schedule.len() / repeat;
+                    if left_out > 0 {
+                        println!(
+                            "warning: classes have different sizes, leaving out {left_out} ciphertexts"
+                        );
+                    }
+                    schedule
+                }
+                None => Schedule::shuffled(ciphertexts.len(), repeat, &mut rng),
+            }
+        }
+        None => Schedule::sequential(ciphertexts.len(), repeat),
+    };
+
+    println!("schedule: {} decryptions", schedule.len());
+
+    Ok((ciphertexts, schedule))
+}
+
+/// Print the memory taken by a run from memory
+fn print_memory(ciphertexts: &[Vec<u8>], schedule: &Schedule, buffers: usize) {
+    let input: usize = ciphertexts.iter().map(Vec::len).sum();
+    let order = schedule.len() * std::mem::size_of::<usize>();
+    println!(
+        "memory: {} (ciphertexts {}, schedule {}, sample buffers {})",
+        mebibytes(input + order + buffers),
+        mebibytes(input),
+        mebibytes(order),
+        mebibytes(buffers)
+    );
+}
+
+/// Destinations of the samples of a measure run
+struct Sinks {
+    output: Box<dyn Sink>,
+    console: Console,
+    plaintexts: Option<PlaintextSink<BufWriter<File>>>,
+    measurements: Option<Measurements>,
+    verifier: Option<Verifier>,
+    online: Option<Online>,
+}
+
+impl Sinks {
+    fn new(
+        args: &MeasureArgs,
+        output_file: File,
+        measurements: Option<Measurements>,
+        implicit_rejection: Option<bool>,
+        resumed: Option<&Checkpoint>,
+        harness: &mut Harness,
+    ) -> Result<Self> {
+        let (classes, labels) = match &measurements {
+            Some(m) => (m.classes().to_vec(), m.labels().to_vec()),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        let output_file = BufWriter::new(output_file);
+        let output: Box<dyn Sink> = match args.format {
+            OutputFormat::Text => {
+                let csv = CsvSink::new(output_file);
+                match resumed {
+                    Some(_) => Box::new(csv.without_header()),
+                    None => Box::new(csv),
+                }
+            }
+            OutputFormat::Binary => {
+                let binary = BinarySink::new(output_file, classes.clone(), labels.clone());
+                match resumed {
+                    Some(_) => Box::new(binary.without_header()),
+                    None => Box::new(binary),
+                }
+            }
+        };
+        let console = Console {
+            stdout: args.stdout.unwrap_or(false),
+            progress: !args.online,
+            count: 0,
+        };
+
+        let online = if args.online {
+            let dudect = Dudect::new(classes, labels, args.leak_threshold, args.max_samples);
+
+            println!("online test: {} classes", dudect.classes().len());
+            harness
+                .metadata_mut()
+                .push("leak-threshold", args.leak_threshold);
+            if let Some(max) = args.max_samples {
+                harness.metadata_mut().push("max-samples", max);
+            }
+
+            Some(Online(dudect))
+        } else {
+            None
+        };
+
+        // Repeats are always checked for consistent results
+        let mut verifier = match &args.expected {
+            Some(path) => Some(Verifier::new(
+                Some(verify::read_expected(path)?),
+                implicit_rejection == Some(true),
+            )),
+            None if args.repeat > 1 => Some(Verifier::new(None, implicit_rejection == Some(true))),
+            None => None,
+        };
+
+        if let (Some(verifier), Some(state)) =
+            (&mut verifier, resumed.and_then(|c| c.verifier.as_ref()))
+        {
+            verifier.restore(state)?;
+        }
+
+        let plaintexts = match &args.plaintext_out {
+            Some(path) => Some(PlaintextSink::new(
+                BufWriter::new(open_output(path, resumed.and_then(|c| c.plaintext_len))?),
+                args.plaintext_format,
+            )),
+            None => None,
+        };
+
+        Ok(Sinks {
+            output,
+            console,
+            plaintexts,
+            measurements,
+            verifier,
+            online,
+        })
+    }
+
+    /// Whether any sink looks at the plaintexts
+    fn keep_plaintexts(&self) -> bool {
+        self.console.stdout || self.plaintexts.is_some() || self.verifier.is_some()
+    }
+
+    /// Give the samples of the earlier runs to the sinks keeping state over
+    /// the whole run
+    fn replay(&mut self, output: &str) -> Result<()> {
+        if self.measurements.is_none() && self.online.is_none() {
+            return Ok(());
+        }
+
+        let (_, samples) = harness::read_output(output)?;
+        for sample in samples {
+            if let Some(measurements) = &mut self.measurements {
+                measurements.record(&sample, &[])?;
+            }
+            if let Some(Online(dudect)) = &mut self.online {
+                dudect.record(&sample, &[])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn all(&mut self) -> Vec<&mut dyn Sink> {
+        let mut sinks: Vec<&mut dyn Sink> = vec![self.output.as_mut(), &mut self.console];
+        if let Some(plaintexts) = &mut self.plaintexts {
+            sinks.push(plaintexts);
+        }
+        if let Some(measurements) = &mut self.measurements {
+            sinks.push(measurements);
+        }
+        if let Some(verifier) = &mut self.verifier {
+            sinks.push(verifier);
+        }
+        if let Some(online) = &mut self.online {
+            sinks.push(online);
+        }
+        sinks
+    }
+}
+
+/// Run the schedule on parallel workers, then give their samples to the
+/// sinks worker by worker
+///
+/// Returns the summary of all workers and the cores they ran on.
+fn run_parallel(
+    args: &MeasureArgs,
+    workers: &Workers,
+    cpus: &[Option<usize>],
+    (ciphertexts, schedule): &(Vec<Vec<u8>>, Schedule),
+    harness: &mut Harness,
+    sinks: &mut Sinks,
+) -> Result<(Summary, Vec<usize>)> {
+    // Workers get whole tuples
+    let tuple_size = sinks.measurements.as_ref().map_or(1, |m| m.class_count());
+    let parts = schedule.split(cpus.len(), tuple_size);
+
+    let buffers = parts.iter().map(|p| workers.memory(p.len())).sum();
+    print_memory(ciphertexts, schedule, buffers);
+
+    let metadata = harness.metadata_mut();
+    if let Some(warmup) = args.run.warmup {
+        let count = match warmup {
+            Warmup::Decryptions(count) => count,
+            Warmup::Input => ciphertexts.len(),
+        };
+        metadata.push("warmup", count);
+    }
+    metadata.push("workers", cpus.len());
+    let pinned: Vec<usize> = cpus.iter().flatten().copied().collect();
+    if pinned.is_empty() {
+        metadata.push("cpus", "-");
+    } else {
+        metadata.push("cpus", tuning::format_cpu_list(&pinned));
+    }
+
+    let len = workers.key.size();
+    let outputs = workers.run(cpus, &parts, ciphertexts, |harness| {
+        if let Some(warmup) = args.run.warmup {
+            warm_up(harness, warmup, &args.input, len)?;
+        }
+        Ok(())
+    })?;
+
+    let mut sinks = sinks.all();
+    for sink in sinks.iter_mut() {
+        sink.begin(harness.metadata())?;
+    }
+
+    let mut cores: Vec<usize> = outputs.iter().flat_map(|o| affinity(&o.tuning)).collect();
+    cores.sort_unstable();
+    cores.dedup();
+
+    let mut summary = Summary::default();
+    for output in &outputs {
+        println!(
+            "worker {}: cpu {}, {} decryptions, affinity {}",
+            output.worker,
+            output.cpu.map_or("-".to_owned(), |c| c.to_string()),
+            output.summary.decryptions,
+            output.tuning.get("affinity").unwrap_or("-")
+        );
+        for warning in tuning::warnings(&output.tuning) {
+            println!("warning: worker {}: {warning}", output.worker);
+        }
+        output.replay(&mut sinks)?;
+
+        summary.decryptions += output.summary.decryptions;
+        summary.failures += output.summary.failures;
+        summary.discarded += output.summary.discarded;
+        summary.interrupted |= output.summary.interrupted;
+    }
+
+    for sink in sinks.iter_mut() {
+        sink.finish()?;
+    }
+
+    Ok((summary, cores))
+}
+
+/// Print the verdict of the online test, and return it for the sidecar
+fn report_online(dudect: &Dudect, stopped: bool) -> Value {
+    // The verdict rests on the |t| which went over the threshold, later
+    // samples of a buffered chunk may have lowered it again
+    let max = dudect.leak().unwrap_or_else(|| dudect.max_t());
+    let short = dudect.short_classes();
+
+    println!("max |t|: {}", dudect.describe(&max));
+    if stopped {
+        println!("stopped after {} samples", dudect.count());
+    }
+    let verdict = if dudect.leak().is_some() {
+        println!("verdict: FAIL, timing leak detected");
+        "FAIL"
+    } else if !short.is_empty() {
+        println!(
+            "verdict: INCONCLUSIVE, fewer than {} samples in class {}",
+            dudect::MIN_SAMPLES,
+            short.join(", ")
+        );
+        "INCONCLUSIVE"
+    } else {
+        println!("verdict: PASS, no timing leak detected");
+        "PASS"
+    };
+
+    json!({
+        "samples": dudect.count(),
+        "max_t": max.t,
+        "classes": [&dudect.classes()[max.a], &dudect.classes()[max.b]],
+        "crop": max.crop,
+        "leak": dudect.leak().is_some(),
+        "verdict": verdict,
+    })
+}
+
+/// Print the results of a finished run, and return the fields they add to
+/// the sidecar
+fn report(
+    args: &MeasureArgs,
+    key: &PrivateKey,
+    summary: &Summary,
+    resumed: Option<&Checkpoint>,
+    sinks: &Sinks,
+) -> Result<Value> {
+    let online = sinks
+        .online
+        .as_ref()
+        .map(|Online(dudect)| report_online(dudect, summary.stopped));
+
+    for mismatch in sinks.verifier.iter().flat_map(Verifier::reported) {
+        println!("mismatch: {mismatch}");
+    }
+    let verification = sinks.verifier.as_ref().map(Verifier::verification);
+    if let Some(v) = &verification {
+        println!(
+            "verified: {} ({} wrong plaintexts, {} unexpected failures, {} unexpected successes, {} nondeterministic)",
+            v.checked, v.wrong_plaintext, v.unexpected_failures, v.unexpected_successes, v.nondeterministic
+        );
+    }
+
+    Ok(json!({
+        "key": {
+            "file": args.key,
+            "bits": key.size() * 8,
+            "sha256": key.fingerprint()?,
+        },
+        "summary": {
+            "decryptions": summary.decryptions,
+            "failures": summary.failures,
+            "discarded": summary.discarded,
+            "stopped": summary.stopped,
+        },
+        "resumed_from": resumed.map(|c| c.samples),
+        "online": online,
+        "verification": verification.map(|v| json!({
+            "checked": v.checked,
+            "wrong_plaintext": v.wrong_plaintext,
+            "unexpected_failures": v.unexpected_failures,
+            "unexpected_successes": v.unexpected_successes,
+            "nondeterministic": v.nondeterministic,
+        })),
+    }))
+}
+
+pub fn measure(args: &MeasureArgs) -> Result<()> {
+    let started = environment::timestamp();
+
+    interrupt::install()?;
+
+    println!("input: {}", args.input);
+    println!("output: {}", args.output);
+    println!("keyfile: {}", args.key);
+
+    let passphrase = match &args.key_pass {
+        Some(source) => Some(key::read_passphrase(source)?),
+        None => None,
+    };
+    let key = PrivateKey::from_file(&args.key, args.key_format, passphrase.as_deref())?;
+    let len = key.size();
+
+    let resumed = if args.resume {
+        Some(resume(args)?)
+    } else {
+        remove_checkpoint(&args.output)?;
+        None
+    };
+
+    let mut source = CiphertextSource::open(&args.input, len)?;
+    let output_file = open_output(&args.output, resumed.as_ref().map(|c| c.output_len))?;
+
+    println!("key length: {} bits ({} bytes)", len * 8, len);
+    println!("padding: {:?}", args.decrypt.padding);
+
+    let config = args.decrypt.config()?;
+
+    // Parallel runs get one worker per core, or unpinned workers
+    let workers = worker_cpus(args)?;
+
+    // Workers set their own real-time priority
+    let tuning = Tuning {
+        fifo_priority: args.run.fifo_priority.filter(|_| workers.is_none()),
+        ..args.run.tuning()
+    };
+    let tuning = tuning.apply()?;
+    print_tuning(&tuning);
+
+    // Cores the measurements ran on, for the environment description
+    let mut cores = affinity(&tuning);
+
+    println!("backend: {:?}", args.decrypt.backend);
+
+    let backend = key.decrypter(args.decrypt.backend, &config)?;
+
+    let implicit_rejection = backend.implicit_rejection();
+    let mode = implicit_rejection_mode(implicit_rejection);
+
+    println!("implicit rejection: {mode}");
+
+    let mut harness = Harness::new(backend, Clock::new(args.decrypt.clock)?);
+    let metadata = harness.metadata();
+
+    println!(
+        "clock: {} (overhead {} {})",
+        metadata.get("clock").unwrap_or_default(),
+        metadata.get("clock-overhead").unwrap_or_default(),
+        harness.unit()
+    );
+
+    harness.metadata_mut().push("key-bits", len * 8);
+    harness
+        .metadata_mut()
+        .push("key-sha256", key.fingerprint()?);
+    harness.metadata_mut().append(&tuning);
+
+    if args.decrypt.padding == PaddingMode::Pkcs1 {
+        harness.metadata_mut().push("implicit-rejection", mode);
+    }
+
+    let measurements = match &args.labels {
+        Some(labels) => Some(Measurements::new(measurements::read_labels(labels)?)),
+        None => None,
+    };
+
+    if let Some(measurements) = &measurements {
+        if !args
+            .run
+            .discard_first
+            .is_multiple_of(measurements.class_count().max(1))
+        {
+            bail!(
+                "--discard-first must be a multiple of the {} classes to keep whole tuples",
+                measurements.class_count()
+            );
+        }
+    }
+
+    if let (Some(warmup), None) = (args.run.warmup, &workers) {
+        let count = warm_up(&mut harness, warmup, &args.input, len)?;
+        println!("warm-up: {count} decryptions");
+        harness.metadata_mut().push("warmup", count);
+    }
+
+    if args.run.discard_first > 0 {
+        let discarded = resumed.as_ref().map_or(0, |c| c.discarded);
+        harness.set_discard_first(args.run.discard_first.saturating_sub(discarded));
+        harness
+            .metadata_mut()
+            .push("discard-first", args.run.discard_first);
+    }
+
+    // Repeated, shuffled, buffered and parallel runs decrypt from memory, in
+    // schedule order
+    let shuffle_seed = shuffle_seed(args, resumed.as_ref())?;
+    let repeat = args.repeat as usize;
+    let buffered = args.buffered || args.flush_every.is_some();
+
+    let scheduled = if repeat > 1 || shuffle_seed.is_some() || buffered || workers.is_some() {
+        let scheduled = schedule(source.by_ref(), repeat, shuffle_seed, measurements.as_ref())?;
+
+        harness.metadata_mut().push("repeat", repeat);
+        if let Some(seed) = shuffle_seed {
+            harness.metadata_mut().push("shuffle-seed", seed);
+        }
+
+        Some(scheduled)
+    } else {
+        None
+    };
+
+    let mut sinks = Sinks::new(
+        args,
+        output_file,
+        measurements,
+        implicit_rejection,
+        resumed.as_ref(),
+        &mut harness,
+    )?;
+    let plaintext_size = if sinks.keep_plaintexts() { len } else { 0 };
+
+    if let (true, None, Some((ciphertexts, schedule))) = (buffered, &workers, &scheduled) {
+        let chunk = args.flush_every.map_or(schedule.len(), |c| c as usize);
+        let buffering = Buffering {
+            chunk: chunk.min(schedule.len()),
+            plaintext_size,
+        };
+        print_memory(ciphertexts, schedule, buffering.memory());
+
+        harness.metadata_mut().push("flush-every", buffering.chunk);
+        harness.set_buffering(Some(buffering));
+    }
+
+    // Sinks keeping state over the whole run get the samples of the earlier
+    // runs first
+    if resumed.is_some() {
+        sinks.replay(&args.output)?;
+    }
+
+    let position = resumed.as_ref().map_or(0, |c| c.position);
+    let summary = match (&scheduled, &workers) {
+        (Some(scheduled), Some(cpus)) => {
+            let workers = Workers {
+                key: &key,
+                backend: args.decrypt.backend,
+                config: &config,
+                clock: args.decrypt.clock,
+                fifo_priority: args.run.fifo_priority,
+                mlock: args.run.mlock,
+                discard_first: args.run.discard_first,
+                plaintext_size,
+            };
+            let (summary, worker_cores) =
+                run_parallel(args, &workers, cpus, scheduled, &mut harness, &mut sinks)?;
+            cores = worker_cores;
+            summary
+        }
+        (Some((ciphertexts, schedule)), None) => {
+            harness.run_indexed(schedule.iter(ciphertexts).skip(position), &mut sinks.all())?
+        }
+        (None, _) => {
+            let source = source
+                .enumerate()
+                .skip(position)
+                .map(|(index, ciphertext)| ciphertext.map(|c| (index, c)));
+            harness.run_indexed(source, &mut sinks.all())?
+        }
+    };
+
+    if summary.decryptions == 0 && resumed.is_none() {
+        bail!("Failed to read input file: too small");
+    }
+
+    println!(
+        "decryptions: {} ({} failed, {} discarded)",
+        summary.decryptions, summary.failures, summary.discarded
+    );
+
+    if summary.interrupted && workers.is_some() {
+        bail!(
+            "Interrupted after {} samples, parallel runs cannot be resumed",
+            summary.decryptions
+        );
+    }
+
+    if summary.interrupted {
+        let checkpoint = save_checkpoint(
+            args,
+            resumed,
+            shuffle_seed,
+            &summary,
+            sinks.verifier.as_ref(),
+        )?;
+        bail!(
+            "Interrupted after {} samples, continue with --resume",
+            checkpoint.samples
+        );
+    }
+
+    remove_checkpoint(&args.output)?;
+
+    if let (Some(measurements), Some(path)) = (&sinks.measurements, &args.measurements) {
+        write_measurements(measurements, path, args.layout, harness.unit())?;
+    }
+
+    let fields = report(args, &key, &summary, resumed.as_ref(), &sinks)?;
+    let sidecar = sidecar(&started, &cores, &args.decrypt, harness.metadata(), fields);
+
+    write_sidecar(&format!("{}.json", args.output), &sidecar)?;
+
+    let verification = sinks.verifier.as_ref().map(Verifier::verification);
+    if let Some(v) = verification.filter(|v| v.mismatches() > 0) {
+        bail!(
+            "Decryption results do not match: {} mismatches",
+            v.mismatches()
+        );
+    }
+
+    Ok(())
+}
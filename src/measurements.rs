@@ -0,0 +1,202 @@
+use crate::clock::Unit;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+};
+
+/// Layout of the class-labelled measurement file
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// One column per class, one row per tuple (tlsfuzzer `timing.csv`)
+    Wide,
+    /// One `block,group,value` row per sample (tlsfuzzer `measurements.csv`)
+    Long,
+}
+
+/// Read a class label file, one label per line
+pub fn read_labels(path: &str) -> Result<Vec<String>> {
+    let file = File::open(path).context("Failed to open labels file")?;
+
+    let mut labels = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.context("Failed to read labels file")?;
+        let label = line.trim();
+        if !label.is_empty() {
+            labels.push(label.to_owned());
+        }
+    }
+
+    Ok(labels)
+}
+
+/// Durations grouped by class label
+///
+/// Samples are grouped in tuples, where each tuple holds exactly one sample
+/// of every class, as produced by the `generate` subcommand.
+pub struct Measurements {
+    /// Class names, in order of first appearance
+    classes: Vec<String>,
+    /// Class index of every ciphertext in the input
+    labels: Vec<usize>,
+    /// Class index and duration of every sample
+    samples: Vec<(usize, u64)>,
+}
+
+impl Measurements {
+    pub fn new(labels: Vec<String>) -> Self {
+        let mut classes: Vec<String> = Vec::new();
+        let labels = labels
+            .into_iter()
+            .map(|label| match classes.iter().position(|c| *c == label) {
+                Some(i) => i,
+                None => {
+                    classes.push(label);
+                    classes.len() - 1
+                }
+            })
+            .collect();
+
+        Measurements {
+            classes,
+            labels,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Measurements of samples whose class index is already known, see
+    /// [`Measurements::push_class`]
+    pub fn with_classes(classes: Vec<String>) -> Self {
+        Measurements {
+            classes,
+            labels: Vec::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Class names, in order of first appearance
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+
+    /// Number of distinct classes, which is also the tuple size
+    pub fn class_count(&self) -> usize {
+        self.classes.len()
+    }
+
+    /// Class index of every ciphertext in the input
+    pub fn labels(&self) -> &[usize] {
+        &self.labels
+    }
+
+    /// Number of recorded samples
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Record the duration of the ciphertext at `index` in the input
+    pub fn push(&mut self, index: usize, duration: u64) -> Result<()> {
+        let Some(&class) = self.labels.get(index) else {
+            bail!("No class label for ciphertext {index}");
+        };
+
+        self.samples.push((class, duration));
+        Ok(())
+    }
+
+    /// Record a duration of the class at `class` in [`Measurements::classes`]
+    pub fn push_class(&mut self, class: usize, duration: u64) -> Result<()> {
+        if class >= self.classes.len() {
+            bail!("Invalid class index {class}");
+        }
+
+        self.samples.push((class, duration));
+        Ok(())
+    }
+
+    /// Group the samples in tuples, dropping a trailing incomplete tuple
+    fn tuples(&self) -> Result<Vec<Vec<u64>>> {
+        let mut tuples = Vec::new();
+        let mut current: Vec<Option<u64>> = vec![None; self.classes.len()];
+        let mut filled = 0;
+
+        for &(class, duration) in &self.samples {
+            if current[class].is_some() {
+                bail!(
+                    "Class {} repeated within a tuple at block {}",
+                    self.classes[class],
+                    tuples.len()
+                );
+            }
+
+            current[class] = Some(duration);
+            filled += 1;
+
+            if filled == self.classes.len() {
+                tuples.push(current.iter().flatten().copied().collect());
+                current.fill(None);
+                filled = 0;
+            }
+        }
+
+        Ok(tuples)
+    }
+
+    /// Write the measurements in the given layout, returning the number of
+    /// tuples written
+    ///
+    /// Durations in nanoseconds are converted to seconds, other units are
+    /// written as raw counts.
+    pub fn write(&self, path: &str, layout: Layout, unit: Unit) -> Result<usize> {
+        let tuples = self.tuples()?;
+
+        let mut file =
+            BufWriter::new(File::create(path).context("Failed to create measurements file")?);
+
+        match layout {
+            Layout::Wide => {
+                writeln!(&mut file, "{}", self.classes.join(","))
+                    .context("failed to write measurements")?;
+
+                for tuple in &tuples {
+                    let row: Vec<String> = tuple.iter().map(|&d| format_value(d, unit)).collect();
+                    writeln!(&mut file, "{}", row.join(","))
+                        .context("failed to write measurements")?;
+                }
+            }
+            Layout::Long => {
+                for (block, tuple) in tuples.iter().enumerate() {
+                    for (group, &duration) in tuple.iter().enumerate() {
+                        writeln!(
+                            &mut file,
+                            "{block},{group},{}",
+                            format_value(duration, unit)
+                        )
+                        .context("failed to write measurements")?;
+                    }
+                }
+            }
+        }
+
+        file.flush().context("failed to write measurements")?;
+
+        Ok(tuples.len())
+    }
+}
+
+/// Format a duration, as seconds when in nanoseconds like tlsfuzzer expects
+fn format_value(duration: u64, unit: Unit) -> String {
+    match unit {
+        Unit::Nanoseconds => format!(
+            "{}.{:09}",
+            duration / 1_000_000_000,
+            duration % 1_000_000_000
+        ),
+        Unit::Ticks | Unit::Cycles => duration.to_string(),
+    }
+}
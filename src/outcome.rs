@@ -0,0 +1,40 @@
+use anyhow::{bail, Context};
+use std::{fmt, str::FromStr};
+
+/// Result of a single decryption call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The ciphertext was decrypted successfully
+    Ok,
+    /// The padding check failed
+    Padding,
+    /// Any other error, identified by a backend specific code
+    Error(u32),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Ok => write!(f, "ok"),
+            Outcome::Padding => write!(f, "padding"),
+            Outcome::Error(code) => write!(f, "error:{code:08X}"),
+        }
+    }
+}
+
+impl FromStr for Outcome {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ok" => Ok(Outcome::Ok),
+            "padding" => Ok(Outcome::Padding),
+            _ => match s.strip_prefix("error:") {
+                Some(code) => Ok(Outcome::Error(
+                    u32::from_str_radix(code, 16).context("Invalid error code")?,
+                )),
+                None => bail!("Unknown outcome {s}"),
+            },
+        }
+    }
+}
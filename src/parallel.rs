@@ -0,0 +1,177 @@
+//! Measurements spread over several worker threads, each pinned to its own
+//! core with its own backend, clock and share of the schedule
+
+use crate::{
+    backend::{BackendKind, Config},
+    clock::{Clock, ClockSource},
+    harness::{Buffering, Harness, Metadata, Sample, Sink, Summary},
+    key::PrivateKey,
+    schedule::Schedule,
+    tuning::Tuning,
+};
+use anyhow::{anyhow, Result};
+use std::thread;
+
+/// Settings shared by all workers of a parallel run
+pub struct Workers<'a> {
+    pub key: &'a PrivateKey,
+    pub backend: BackendKind,
+    pub config: &'a Config,
+    pub clock: ClockSource,
+    pub fifo_priority: Option<i32>,
+    pub mlock: bool,
+    /// Samples every worker drops at the start of its run
+    pub discard_first: usize,
+    /// Bytes kept for the plaintext of every sample, 0 to drop them
+    pub plaintext_size: usize,
+}
+
+/// Samples of one worker, kept in memory until all workers are done
+pub struct Output {
+    pub worker: usize,
+    pub cpu: Option<usize>,
+    pub summary: Summary,
+    /// Effective scheduling settings of the worker thread
+    pub tuning: Metadata,
+    samples: Samples,
+}
+
+impl Output {
+    /// Report the samples to the sinks of the run, in the order the worker
+    /// took them
+    pub fn replay(&self, sinks: &mut [&mut dyn Sink]) -> Result<()> {
+        let samples = &self.samples;
+
+        for (i, sample) in samples.samples.iter().enumerate() {
+            let plaintext = match samples.plaintext_size {
+                0 => &[][..],
+                size => &samples.plaintexts[i * size..][..sample.len.min(size)],
+            };
+            for sink in sinks.iter_mut() {
+                sink.record(sample, plaintext)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sink of a worker, copying the samples out of the harness buffers after
+/// the run
+struct Samples {
+    samples: Vec<Sample>,
+    plaintexts: Vec<u8>,
+    plaintext_size: usize,
+}
+
+impl Sink for Samples {
+    fn record(&mut self, sample: &Sample, plaintext: &[u8]) -> Result<()> {
+        self.samples.push(*sample);
+
+        if self.plaintext_size > 0 {
+            let start = self.plaintexts.len();
+            self.plaintexts.extend_from_slice(plaintext);
+            self.plaintexts.resize(start + self.plaintext_size, 0);
+        }
+
+        Ok(())
+    }
+}
+
+impl Workers<'_> {
+    /// Memory taken by the samples of a worker measuring `decryptions`
+    /// ciphertexts: its harness buffer and the copy it hands back
+    pub fn memory(&self, decryptions: usize) -> usize {
+        let buffering = Buffering {
+            chunk: decryptions,
+            plaintext_size: self.plaintext_size,
+        };
+        2 * buffering.memory()
+    }
+
+    /// Run one worker per entry of `cpus`, `None` leaving a worker unpinned,
+    /// over the matching part of the schedule
+    ///
+    /// `prepare` runs in every worker before its measurements, e.g. to warm
+    /// up its backend.
+    pub fn run<F>(
+        &self,
+        cpus: &[Option<usize>],
+        schedules: &[Schedule],
+        ciphertexts: &[Vec<u8>],
+        prepare: F,
+    ) -> Result<Vec<Output>>
+    where
+        F: Fn(&mut Harness) -> Result<()> + Sync,
+    {
+        let prepare = &prepare;
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = cpus
+                .iter()
+                .zip(schedules)
+                .enumerate()
+                .map(|(worker, (&cpu, schedule))| {
+                    scope.spawn(move || self.worker(worker, cpu, schedule, ciphertexts, prepare))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .map_err(|_| anyhow!("Worker thread panicked"))?
+                })
+                .collect()
+        })
+    }
+
+    fn worker<F>(
+        &self,
+        worker: usize,
+        cpu: Option<usize>,
+        schedule: &Schedule,
+        ciphertexts: &[Vec<u8>],
+        prepare: &F,
+    ) -> Result<Output>
+    where
+        F: Fn(&mut Harness) -> Result<()>,
+    {
+        let tuning = Tuning {
+            cpu,
+            fifo_priority: self.fifo_priority,
+            mlock: self.mlock,
+        };
+        let tuning = tuning.apply()?;
+
+        let backend = self.key.decrypter(self.backend, self.config)?;
+        let mut harness = Harness::new(backend, Clock::new(self.clock)?);
+        harness.set_worker(worker, cpu);
+        harness.set_discard_first(self.discard_first);
+
+        // Workers share the console and the output files, so their samples
+        // stay in memory until the end of the run
+        harness.set_buffering(Some(Buffering {
+            chunk: schedule.len(),
+            plaintext_size: self.plaintext_size,
+        }));
+
+        prepare(&mut harness)?;
+
+        let mut samples = Samples {
+            samples: Vec::with_capacity(schedule.len()),
+            plaintexts: Vec::with_capacity(schedule.len() * self.plaintext_size),
+            plaintext_size: self.plaintext_size,
+        };
+        let summary = harness.run_indexed(schedule.iter(ciphertexts), &mut [&mut samples])?;
+
+        Ok(Output {
+            worker,
+            cpu,
+            summary,
+            tuning,
+            samples,
+        })
+    }
+}
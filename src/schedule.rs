@@ -0,0 +1,204 @@
+use anyhow::{bail, Result};
+use rand::{seq::SliceRandom, Rng};
+
+/// Order in which the ciphertexts of an input are decrypted
+///
+/// Measuring every ciphertext several times in a random order spreads drift,
+/// like frequency or temperature changes, evenly over all of them.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    order: Vec<usize>,
+}
+
+impl Schedule {
+    /// Every ciphertext `repeat` times, in file order
+    pub fn sequential(count: usize, repeat: usize) -> Self {
+        Schedule {
+            order: (0..repeat).flat_map(|_| 0..count).collect(),
+        }
+    }
+
+    /// Every ciphertext `repeat` times, in a random order
+    pub fn shuffled<R: Rng>(count: usize, repeat: usize, rng: &mut R) -> Self {
+        let mut schedule = Self::sequential(count, repeat);
+        schedule.order.shuffle(rng);
+        schedule
+    }
+
+    /// Every ciphertext `repeat` times, in random tuples holding one
+    /// ciphertext of each class, in a random order within the tuple
+    ///
+    /// `labels` holds the class index of every ciphertext. The n-th
+    /// ciphertexts of all classes form the n-th tuple, ciphertexts beyond the
+    /// size of the smallest class are left out, which shows in
+    /// [`Schedule::len`].
+    pub fn shuffled_tuples<R: Rng>(labels: &[usize], repeat: usize, rng: &mut R) -> Result<Self> {
+        let classes = labels.iter().max().map_or(0, |&c| c + 1);
+
+        let mut by_class: Vec<Vec<usize>> = vec![Vec::new(); classes];
+        for (index, &class) in labels.iter().enumerate() {
+            by_class[class].push(index);
+        }
+
+        let tuples = by_class.iter().map(Vec::len).min().unwrap_or(0);
+        if tuples == 0 {
+            bail!("No complete tuple of classes in the input");
+        }
+
+        let mut blocks: Vec<usize> = (0..repeat).flat_map(|_| 0..tuples).collect();
+        blocks.shuffle(rng);
+
+        let mut order = Vec::with_capacity(blocks.len() * classes);
+        for block in blocks {
+            let start = order.len();
+            order.extend(by_class.iter().map(|c| c[block]));
+            order[start..].shuffle(rng);
+        }
+
+        Ok(Schedule { order })
+    }
+
+    /// Several inputs, of `counts[i]` ciphertexts each, measured in rounds
+    /// holding the n-th ciphertext of every input, in a random order within
+    /// the round, `repeat` times
+    ///
+    /// Indices run over the inputs placed one after the other.
+    pub fn interleaved<R: Rng>(counts: &[usize], repeat: usize, rng: &mut R) -> Self {
+        let offsets: Vec<usize> = counts
+            .iter()
+            .scan(0, |offset, &count| {
+                let start = *offset;
+                *offset += count;
+                Some(start)
+            })
+            .collect();
+        let rounds = counts.iter().copied().max().unwrap_or(0);
+
+        let mut order = Vec::with_capacity(repeat * counts.iter().sum::<usize>());
+        for _ in 0..repeat {
+            for round in 0..rounds {
+                let start = order.len();
+                order.extend(
+                    counts
+                        .iter()
+                        .zip(&offsets)
+                        .filter(|(&count, _)| round < count)
+                        .map(|(_, &offset)| offset + round),
+                );
+                order[start..].shuffle(rng);
+            }
+        }
+
+        Schedule { order }
+    }
+
+    /// Deal the schedule out to `parts` workers, in blocks of `block`
+    /// consecutive decryptions like the tuples of a shuffled schedule
+    ///
+    /// Blocks go to the workers in turn, so that every worker gets a share of
+    /// the whole schedule.
+    pub fn split(&self, parts: usize, block: usize) -> Vec<Schedule> {
+        let mut schedules = vec![Schedule { order: Vec::new() }; parts];
+
+        for (i, block) in self.order.chunks(block.max(1)).enumerate() {
+            schedules[i % parts].order.extend_from_slice(block);
+        }
+
+        schedules
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Ciphertexts in schedule order, with their index in the input
+    pub fn iter<'a>(
+        &'a self,
+        ciphertexts: &'a [Vec<u8>],
+    ) -> impl Iterator<Item = Result<(usize, &'a [u8])>> + 'a {
+        self.order
+            .iter()
+            .map(move |&index| Ok((index, ciphertexts[index].as_slice())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn interleaved_rounds() {
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let schedule = Schedule::interleaved(&[3, 1, 2], 2, &mut rng);
+
+        // Inputs start at 0, 3 and 4, rounds shrink as inputs run out
+        let rounds = [vec![0, 3, 4], vec![1, 5], vec![2]];
+        let mut order = schedule.order.as_slice();
+        for _ in 0..2 {
+            for round in &rounds {
+                let (current, rest) = order.split_at(round.len());
+                let mut current = current.to_vec();
+                current.sort_unstable();
+                assert_eq!(&current, round);
+                order = rest;
+            }
+        }
+        assert!(order.is_empty());
+    }
+
+    #[test]
+    fn interleaved_without_inputs() {
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+
+        assert!(Schedule::interleaved(&[], 3, &mut rng).is_empty());
+        assert!(Schedule::interleaved(&[0, 0], 3, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn split_deals_blocks_in_turn() {
+        let parts = Schedule::sequential(10, 1).split(3, 2);
+
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].order, [0, 1, 6, 7]);
+        assert_eq!(parts[1].order, [2, 3, 8, 9]);
+        assert_eq!(parts[2].order, [4, 5]);
+    }
+
+    #[test]
+    fn split_keeps_tuples_together() {
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let labels = [0, 1, 2, 0, 1, 2, 0, 1, 2];
+        let schedule = Schedule::shuffled_tuples(&labels, 4, &mut rng).unwrap();
+
+        let parts = schedule.split(2, 3);
+        for part in &parts {
+            for tuple in part.order.chunks(3) {
+                let mut classes: Vec<usize> = tuple.iter().map(|&i| labels[i]).collect();
+                classes.sort_unstable();
+                assert_eq!(classes, [0, 1, 2]);
+            }
+        }
+
+        let mut all: Vec<usize> = parts.iter().flat_map(|p| p.order.clone()).collect();
+        let mut expected = schedule.order.clone();
+        all.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn split_with_more_parts_than_blocks() {
+        let parts = Schedule::sequential(2, 1).split(4, 0);
+
+        assert_eq!(
+            parts.iter().map(Schedule::len).collect::<Vec<_>>(),
+            [1, 1, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::{BufReader, ErrorKind, Read},
+};
+
+/// Ciphertexts read back to back from a file or any other reader
+///
+/// Iteration stops at the end of the input, a trailing partial ciphertext
+/// is ignored.
+pub struct CiphertextSource<R> {
+    reader: R,
+    size: usize,
+}
+
+impl CiphertextSource<BufReader<File>> {
+    /// Open a file of `size` bytes ciphertexts
+    pub fn open(path: &str, size: usize) -> Result<Self> {
+        let file = File::open(path).context("Failed to open input file")?;
+        Ok(Self::new(BufReader::new(file), size))
+    }
+}
+
+impl<R: Read> CiphertextSource<R> {
+    pub fn new(reader: R, size: usize) -> Self {
+        CiphertextSource { reader, size }
+    }
+}
+
+impl<R: Read> Iterator for CiphertextSource<R> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut ciphertext = vec![0; self.size];
+
+        match self.reader.read_exact(&mut ciphertext) {
+            Ok(()) => Some(Ok(ciphertext)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e).context("Failed to read input file")),
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use rand::Rng;
+use statrs::distribution::{Binomial, ChiSquared, ContinuousCDF, DiscreteCDF, Normal, StudentsT};
+
+/// Two sided p-value of the sign test on paired samples
+pub fn sign_test(a: &[f64], b: &[f64]) -> f64 {
+    let (mut less, mut greater) = (0u64, 0u64);
+    for (x, y) in a.iter().zip(b) {
+        if x < y {
+            less += 1;
+        } else if x > y {
+            greater += 1;
+        }
+    }
+
+    let n = less + greater;
+    if n == 0 {
+        return 1.0;
+    }
+
+    let binomial = Binomial::new(0.5, n).expect("valid binomial parameters");
+    let p = 2.0 * binomial.cdf(less.min(greater));
+    p.min(1.0)
+}
+
+/// Two sided p-value of the Wilcoxon signed-rank test on paired samples
+///
+/// Uses the normal approximation with tie correction, zero differences are
+/// discarded.
+pub fn wilcoxon(a: &[f64], b: &[f64]) -> f64 {
+    let diffs: Vec<f64> = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| x - y)
+        .filter(|d| *d != 0.0)
+        .collect();
+
+    let n = diffs.len() as f64;
+    if diffs.is_empty() {
+        return 1.0;
+    }
+
+    let magnitudes: Vec<f64> = diffs.iter().map(|d| d.abs()).collect();
+    let (ranks, ties) = rank(&magnitudes);
+
+    let w_plus: f64 = diffs
+        .iter()
+        .zip(&ranks)
+        .filter(|(d, _)| **d > 0.0)
+        .map(|(_, r)| r)
+        .sum();
+
+    let mean = n * (n + 1.0) / 4.0;
+    let var = n * (n + 1.0) * (2.0 * n + 1.0) / 24.0 - ties / 48.0;
+    if var <= 0.0 {
+        return 1.0;
+    }
+
+    let z = (w_plus - mean) / var.sqrt();
+    2.0 * normal_sf(z.abs())
+}
+
+/// Two sided p-value of the paired t-test
+pub fn paired_t_test(a: &[f64], b: &[f64]) -> f64 {
+    let diffs: Vec<f64> = a.iter().zip(b).map(|(x, y)| x - y).collect();
+    let n = diffs.len() as f64;
+    if diffs.len() < 2 {
+        return 1.0;
+    }
+
+    let mean = diffs.iter().sum::<f64>() / n;
+    let var = diffs.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    if var == 0.0 {
+        return if mean == 0.0 { 1.0 } else { 0.0 };
+    }
+
+    let t = mean / (var / n).sqrt();
+    let dist = StudentsT::new(0.0, 1.0, n - 1.0).expect("valid t parameters");
+    2.0 * dist.sf(t.abs())
+}
+
+/// p-value of the Friedman test across all classes
+///
+/// `columns` holds one vector of samples per class, with the samples of the
+/// same tuple at the same position.
+pub fn friedman(columns: &[Vec<f64>]) -> f64 {
+    let k = columns.len();
+    let n = columns.iter().map(Vec::len).min().unwrap_or(0);
+    if k < 2 || n == 0 {
+        return 1.0;
+    }
+
+    let mut rank_sums = vec![0.0; k];
+    let mut ties = 0.0;
+    let mut row = vec![0.0; k];
+
+    for i in 0..n {
+        for (value, column) in row.iter_mut().zip(columns) {
+            *value = column[i];
+        }
+
+        let (ranks, row_ties) = rank(&row);
+        for (sum, r) in rank_sums.iter_mut().zip(ranks) {
+            *sum += r;
+        }
+        ties += row_ties;
+    }
+
+    let (n, k) = (n as f64, k as f64);
+    let sum_squares: f64 = rank_sums.iter().map(|r| r * r).sum();
+    let chi2 = 12.0 / (n * k * (k + 1.0)) * sum_squares - 3.0 * n * (k + 1.0);
+
+    let correction = 1.0 - ties / (n * (k.powi(3) - k));
+    if correction <= 0.0 {
+        return 1.0;
+    }
+
+    let dist = ChiSquared::new(k - 1.0).expect("valid chi-squared parameters");
+    dist.sf(chi2 / correction)
+}
+
+/// Largest number of pairs for which the Spearman p-value is computed
+/// exactly, from all permutations
+const SPEARMAN_EXACT: usize = 9;
+
+/// Spearman rank correlation of paired values and its two sided p-value
+///
+/// The p-value comes from the exact permutation distribution for up to
+/// [`SPEARMAN_EXACT`] pairs and from the t approximation above, constant
+/// inputs are reported as uncorrelated.
+pub fn spearman(x: &[f64], y: &[f64]) -> (f64, f64) {
+    let n = x.len().min(y.len());
+    if n < 3 {
+        return (0.0, 1.0);
+    }
+
+    // Pearson correlation of the average ranks, which accounts for ties
+    let (rx, _) = rank(&x[..n]);
+    let (ry, _) = rank(&y[..n]);
+    let mean = (n as f64 + 1.0) / 2.0;
+    let rx: Vec<f64> = rx.iter().map(|r| r - mean).collect();
+    let mut ry: Vec<f64> = ry.iter().map(|r| r - mean).collect();
+
+    let var_x: f64 = rx.iter().map(|a| a * a).sum();
+    let var_y: f64 = ry.iter().map(|b| b * b).sum();
+    if var_x == 0.0 || var_y == 0.0 {
+        return (0.0, 1.0);
+    }
+
+    let cov = |ry: &[f64]| -> f64 { rx.iter().zip(ry).map(|(a, b)| a * b).sum() };
+    let observed = cov(&ry);
+    let rho = (observed / (var_x * var_y).sqrt()).clamp(-1.0, 1.0);
+
+    if n <= SPEARMAN_EXACT {
+        // Permutations of the ranks keep both variances, so comparing the
+        // covariances is enough; the margin absorbs rounding errors
+        let margin = 1e-9 * var_x.max(var_y);
+        let (mut extreme, mut total) = (0u64, 0u64);
+        permutations(&mut ry, &mut |ry| {
+            total += 1;
+            if cov(ry).abs() >= observed.abs() - margin {
+                extreme += 1;
+            }
+        });
+        return (rho, extreme as f64 / total as f64);
+    }
+
+    if rho.abs() >= 1.0 {
+        // Only the two monotone orderings are as extreme
+        return (rho, spearman_min_p(n));
+    }
+
+    let df = n as f64 - 2.0;
+    let t = rho * (df / (1.0 - rho * rho)).sqrt();
+    let dist = StudentsT::new(0.0, 1.0, df).expect("valid t parameters");
+    (rho, 2.0 * dist.sf(t.abs()))
+}
+
+/// Smallest two sided p-value of the Spearman test on `n` pairs without
+/// ties, reached by a perfect correlation
+pub fn spearman_min_p(n: usize) -> f64 {
+    let orderings: f64 = (1..=n).map(|i| i as f64).product();
+    (2.0 / orderings).clamp(f64::MIN_POSITIVE, 1.0)
+}
+
+/// Call `f` on every permutation of `values` (Heap's algorithm)
+fn permutations(values: &mut [f64], f: &mut impl FnMut(&[f64])) {
+    let n = values.len();
+    let mut counters = vec![0; n];
+
+    f(values);
+    let mut i = 1;
+    while i < n {
+        if counters[i] < i {
+            let j = if i % 2 == 0 { 0 } else { counters[i] };
+            values.swap(j, i);
+            f(values);
+            counters[i] += 1;
+            i = 1;
+        } else {
+            counters[i] = 0;
+            i += 1;
+        }
+    }
+}
+
+/// Average ranks (starting at 1) of the values, and the tie term
+/// `sum(t^3 - t)` over all groups of `t` tied values
+fn rank(values: &[f64]) -> (Vec<f64>, f64) {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_unstable_by(|&i, &j| values[i].total_cmp(&values[j]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut ties = 0.0;
+    let mut start = 0;
+
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+
+        let t = (end - start) as f64;
+        let average = (start + end + 1) as f64 / 2.0;
+        for &i in &order[start..end] {
+            ranks[i] = average;
+        }
+        ties += t.powi(3) - t;
+
+        start = end;
+    }
+
+    (ranks, ties)
+}
+
+/// Survival function of the standard normal distribution
+fn normal_sf(z: f64) -> f64 {
+    Normal::new(0.0, 1.0)
+        .expect("valid normal parameters")
+        .sf(z)
+}
+
+/// Quantile of sorted data, with linear interpolation
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let pos = q * (sorted.len() - 1) as f64;
+    let low = pos.floor() as usize;
+    let high = pos.ceil() as usize;
+    sorted[low] + (sorted[high] - sorted[low]) * (pos - low as f64)
+}
+
+/// Location statistic compared between classes
+#[derive(Clone, Copy, Debug)]
+pub enum Statistic {
+    Median,
+    /// Mean after removing the given fraction of samples from each end
+    TrimmedMean(f64),
+    /// Average of the first and third quartiles
+    MidHinge,
+}
+
+impl Statistic {
+    /// Evaluate the statistic on sorted data
+    pub fn eval(self, sorted: &[f64]) -> f64 {
+        match self {
+            Statistic::Median => quantile(sorted, 0.5),
+            Statistic::TrimmedMean(fraction) => {
+                let cut = (sorted.len() as f64 * fraction) as usize;
+                let kept = &sorted[cut..sorted.len() - cut];
+                kept.iter().sum::<f64>() / kept.len() as f64
+            }
+            Statistic::MidHinge => (quantile(sorted, 0.25) + quantile(sorted, 0.75)) / 2.0,
+        }
+    }
+}
+
+impl std::fmt::Display for Statistic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Statistic::Median => write!(f, "median"),
+            Statistic::TrimmedMean(fraction) => {
+                write!(f, "trimmed mean ({:.0}%)", fraction * 100.0)
+            }
+            Statistic::MidHinge => write!(f, "mid-hinge"),
+        }
+    }
+}
+
+/// Percentile bootstrap confidence intervals of statistics of `data`
+///
+/// Returns, for every statistic, its value on the full sample and the bounds
+/// of the interval at the given confidence level.
+pub fn bootstrap<R: Rng>(
+    data: &[f64],
+    statistics: &[Statistic],
+    resamples: usize,
+    confidence: f64,
+    rng: &mut R,
+) -> Vec<(f64, f64, f64)> {
+    let mut sorted = data.to_vec();
+    sorted.sort_unstable_by(f64::total_cmp);
+
+    let mut estimates = vec![Vec::with_capacity(resamples); statistics.len()];
+    let mut sample = vec![0.0; data.len()];
+
+    for _ in 0..resamples {
+        for value in sample.iter_mut() {
+            *value = data[rng.gen_range(0..data.len())];
+        }
+        sample.sort_unstable_by(f64::total_cmp);
+
+        for (estimates, statistic) in estimates.iter_mut().zip(statistics) {
+            estimates.push(statistic.eval(&sample));
+        }
+    }
+
+    let alpha = 1.0 - confidence;
+    statistics
+        .iter()
+        .zip(estimates.iter_mut())
+        .map(|(statistic, estimates)| {
+            estimates.sort_unstable_by(f64::total_cmp);
+            (
+                statistic.eval(&sorted),
+                quantile(estimates, alpha / 2.0),
+                quantile(estimates, 1.0 - alpha / 2.0),
+            )
+        })
+        .collect()
+}
+
+/// Running mean and variance, updated one value at a time (Welford)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Moments {
+    n: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    pub fn push(&mut self, x: f64) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.n
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Sample variance
+    pub fn variance(&self) -> f64 {
+        if self.n < 2 {
+            0.0
+        } else {
+            self.m2 / (self.n - 1) as f64
+        }
+    }
+}
+
+/// Welch's t statistic of two independent samples, 0 while either has less
+/// than two values or both are constant
+pub fn welch_t(a: &Moments, b: &Moments) -> f64 {
+    if a.n < 2 || b.n < 2 {
+        return 0.0;
+    }
+
+    let se = (a.variance() / a.n as f64 + b.variance() / b.n as f64).sqrt();
+    if se == 0.0 {
+        return 0.0;
+    }
+
+    (a.mean - b.mean) / se
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn spearman_perfect_correlation_is_not_certain() {
+        let (rho, p) = spearman(&[1.0, 2.0, 3.0], &[10.0, 20.0, 30.0]);
+        assert!(close(rho, 1.0));
+        assert!(close(p, 1.0 / 3.0));
+
+        let (rho, p) = spearman(&[1.0, 2.0, 3.0, 4.0], &[4.0, 3.0, 2.0, 1.0]);
+        assert!(close(rho, -1.0));
+        assert!(close(p, 2.0 / 24.0));
+
+        let x: Vec<f64> = (0..12).map(f64::from).collect();
+        let (rho, p) = spearman(&x, &x);
+        assert!(close(rho, 1.0));
+        assert!(p > 0.0);
+    }
+
+    #[test]
+    fn spearman_exact_distribution() {
+        // Of the 24 orderings of 4 ranks, 8 have |rho| >= 0.8
+        let (rho, p) = spearman(&[1.0, 2.0, 3.0, 4.0], &[1.0, 2.0, 4.0, 3.0]);
+        assert!(close(rho, 0.8));
+        assert!(close(p, 8.0 / 24.0));
+    }
+
+    #[test]
+    fn spearman_constant_input() {
+        assert_eq!(spearman(&[1.0, 1.0, 1.0], &[1.0, 2.0, 3.0]), (0.0, 1.0));
+    }
+
+    #[test]
+    fn spearman_min_p_values() {
+        assert!(close(spearman_min_p(3), 1.0 / 3.0));
+        assert!(close(spearman_min_p(6), 2.0 / 720.0));
+        assert!(spearman_min_p(1000) > 0.0);
+    }
+
+    #[test]
+    fn wilcoxon_normal_approximation() {
+        // W+ = 15, mean 7.5, variance 13.75
+        let p = wilcoxon(&[2.0, 4.0, 6.0, 8.0, 10.0], &[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert!(close(p, 2.0 * normal_sf(7.5 / 13.75f64.sqrt())));
+        assert!(close(p, 0.0431));
+
+        assert_eq!(wilcoxon(&[1.0, 2.0], &[1.0, 2.0]), 1.0);
+    }
+
+    #[test]
+    fn friedman_consistent_ranking() {
+        // The same order in all 5 rows gives chi2 = 10 on 2 degrees of
+        // freedom, whose survival function is exp(-5)
+        let columns = vec![vec![1.0; 5], vec![2.0; 5], vec![3.0; 5]];
+        assert!(close(friedman(&columns), (-5.0f64).exp()));
+
+        let tied = vec![vec![1.0; 5], vec![1.0; 5]];
+        assert_eq!(friedman(&tied), 1.0);
+    }
+
+    #[test]
+    fn ranks_with_ties() {
+        let (ranks, ties) = rank(&[10.0, 20.0, 10.0, 30.0]);
+        assert_eq!(ranks, vec![1.5, 3.0, 1.5, 4.0]);
+        assert_eq!(ties, 6.0);
+    }
+
+    #[test]
+    fn welch_t_of_moments() {
+        let mut a = Moments::default();
+        let mut b = Moments::default();
+        for x in [1.0, 2.0, 3.0] {
+            a.push(x);
+            b.push(x + 3.0);
+        }
+        assert!(close(a.mean(), 2.0));
+        assert!(close(a.variance(), 1.0));
+        // (2 - 5) / sqrt(1/3 + 1/3)
+        assert!(close(welch_t(&a, &b), -3.0 / (2.0f64 / 3.0).sqrt()));
+    }
+}
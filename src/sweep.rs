@@ -0,0 +1,560 @@
+use crate::measure::{self, DecryptArgs, RunArgs, Warmup};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rsa_decrypt_timing::{
+    backend::{Backend, Decryption, PaddingMode},
+    clock::Clock,
+    environment,
+    harness::{Harness, Metadata, Sample, Sink},
+    interrupt,
+    key::{KeyFormat, PrivateKey},
+    measurements,
+    outcome::Outcome,
+    schedule::Schedule,
+    source::CiphertextSource,
+    stats::{self, Statistic},
+};
+use serde_json::json;
+use std::{
+    cell::Cell,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// Measure several keys in interleaved order and look for key-dependent
+/// timing
+#[derive(Args, Debug)]
+pub struct SweepArgs {
+    /// Key files, or directories whose `*.pem` files are all used
+    #[arg(short = 'k', long, num_args = 1.., required = true)]
+    keys: Vec<String>,
+
+    /// Extension of the ciphertext file next to each key, `key.pem` using
+    /// `key.bin` by default
+    #[arg(long, default_value = "bin")]
+    extension: String,
+
+    /// Extension of the class label file next to each key, e.g. `labels`;
+    /// the spread between the classes is then compared instead of the
+    /// difference between failed and successful decryptions
+    #[arg(short = 'l', long)]
+    labels: Option<String>,
+
+    /// Output file, one `key,index,duration,outcome` line per sample
+    #[arg(short = 'o', long)]
+    output: String,
+
+    #[command(flatten)]
+    decrypt: DecryptArgs,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Number of passes over the ciphertexts of every key
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    repeat: u64,
+
+    /// Seed for the interleaving order, for reproducible runs
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Significance level of the correlation tests
+    #[arg(short = 'a', long, default_value_t = 0.05)]
+    alpha: f64,
+}
+
+/// Key properties Marvin-class bugs have been seen to depend on
+struct Properties {
+    /// Exact modulus length in bits
+    bits: u32,
+    /// Modulus length in bytes
+    bytes: usize,
+    /// Zero bits at the top of the most significant modulus byte
+    top_zero_bits: usize,
+    /// Leading zero bytes of the private exponent, padded to the modulus size
+    d_zero_bytes: usize,
+    p_bits: i32,
+    q_bits: i32,
+}
+
+impl Properties {
+    fn new(key: &PrivateKey) -> Result<Self> {
+        let rsa = key
+            .pkey()
+            .rsa()
+            .context("Failed getting RSA key from PKey")?;
+        let bits = rsa.n().num_bits() as u32;
+        let bytes = key.size();
+
+        Ok(Properties {
+            bits,
+            bytes,
+            top_zero_bits: bytes * 8 - bits as usize,
+            d_zero_bytes: bytes - rsa.d().num_bytes() as usize,
+            p_bits: rsa.p().map_or(0, |p| p.num_bits()),
+            q_bits: rsa.q().map_or(0, |q| q.num_bits()),
+        })
+    }
+
+    /// Name and value of every property, for the correlation tests
+    fn values(&self) -> [(&'static str, f64); 6] {
+        [
+            ("modulus bits", self.bits as f64),
+            ("modulus bytes", self.bytes as f64),
+            ("top zero bits", self.top_zero_bits as f64),
+            ("d zero bytes", self.d_zero_bytes as f64),
+            ("p bits", self.p_bits as f64),
+            ("q bits", self.q_bits as f64),
+        ]
+    }
+}
+
+/// One key of the sweep with its ciphertexts and samples
+struct Subject {
+    name: String,
+    properties: Properties,
+    fingerprint: String,
+    /// Index of its first ciphertext in the inputs of all keys
+    offset: usize,
+    ciphertexts: usize,
+    /// Class label of every ciphertext, empty without `--labels`
+    labels: Vec<String>,
+    samples: Vec<Sample>,
+}
+
+impl Subject {
+    /// Median duration of the samples matching `filter`
+    fn median(&self, filter: impl Fn(&Sample) -> bool) -> Option<f64> {
+        let mut durations: Vec<f64> = self
+            .samples
+            .iter()
+            .filter(|s| filter(s))
+            .map(|s| s.duration as f64)
+            .collect();
+        if durations.is_empty() {
+            return None;
+        }
+
+        durations.sort_unstable_by(f64::total_cmp);
+        Some(Statistic::Median.eval(&durations))
+    }
+
+    /// Difference between the median durations of the classes, failed minus
+    /// successful decryptions without labels, the largest minus the smallest
+    /// class median with labels
+    fn difference(&self) -> Option<f64> {
+        if self.labels.is_empty() {
+            let ok = self.median(|s| s.outcome == Outcome::Ok)?;
+            let failed = self.median(|s| s.outcome != Outcome::Ok)?;
+            return Some(failed - ok);
+        }
+
+        let mut classes: Vec<&String> = self.labels.iter().collect();
+        classes.sort_unstable();
+        classes.dedup();
+
+        let medians: Vec<f64> = classes
+            .iter()
+            .filter_map(|&class| self.median(|s| self.labels.get(s.index) == Some(class)))
+            .collect();
+        if medians.len() < 2 {
+            return None;
+        }
+
+        let max = medians.iter().copied().fold(f64::MIN, f64::max);
+        let min = medians.iter().copied().fold(f64::MAX, f64::min);
+        Some(max - min)
+    }
+}
+
+/// Backends of all keys, decrypting with the key of the ciphertext being
+/// measured
+///
+/// The harness only hands the ciphertext to its backend, so the source of
+/// the run selects the key through `current` before yielding a ciphertext.
+struct Keyed {
+    backends: Vec<Box<dyn Backend>>,
+    current: Rc<Cell<usize>>,
+}
+
+impl Backend for Keyed {
+    fn decrypt(&mut self, ciphertext: &[u8], plaintext: &mut [u8]) -> Result<Decryption> {
+        self.backends[self.current.get()].decrypt(ciphertext, plaintext)
+    }
+
+    fn complete(&mut self, decryption: &mut Decryption, plaintext: &mut [u8]) -> Result<()> {
+        self.backends[self.current.get()].complete(decryption, plaintext)
+    }
+
+    fn implicit_rejection(&self) -> Option<bool> {
+        self.backends[0].implicit_rejection()
+    }
+
+    fn self_timed(&self) -> bool {
+        self.backends[0].self_timed()
+    }
+}
+
+/// Write samples as `key,index,duration,outcome` lines, after the metadata
+/// header, and keep them with their key for the report
+struct SweepSink<'a, W> {
+    writer: W,
+    subjects: &'a mut [Subject],
+    /// Key of every ciphertext in the inputs of all keys
+    owners: &'a [usize],
+}
+
+impl<W: Write> Sink for SweepSink<'_, W> {
+    fn begin(&mut self, metadata: &Metadata) -> Result<()> {
+        for (name, value) in metadata.iter() {
+            writeln!(self.writer, "# {name}: {value}").context("failed to write output header")?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, sample: &Sample, _plaintext: &[u8]) -> Result<()> {
+        let subject = &mut self.subjects[self.owners[sample.index]];
+        let sample = Sample {
+            index: sample.index - subject.offset,
+            ..*sample
+        };
+
+        writeln!(
+            self.writer,
+            "{},{},{},{}",
+            subject.name, sample.index, sample.duration, sample.outcome
+        )
+        .context("failed to write duration")?;
+        subject.samples.push(sample);
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush().context("failed to write duration")
+    }
+}
+
+/// Expand directories to the key files they hold, sorted by name
+fn key_files(paths: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        let path = Path::new(path);
+        if !path.is_dir() {
+            files.push(path.to_owned());
+            continue;
+        }
+
+        let mut keys: Vec<PathBuf> = fs::read_dir(path)
+            .with_context(|| format!("Failed to read key directory {}", path.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "pem"))
+            .collect();
+        keys.sort();
+        files.extend(keys);
+    }
+
+    Ok(files)
+}
+
+/// Smallest number of keys for which the correlation tests can report a
+/// p-value below `threshold`
+fn keys_needed(threshold: f64) -> usize {
+    (3..)
+        .find(|&n| stats::spearman_min_p(n) < threshold)
+        .unwrap()
+}
+
+/// Print the correlations of a per-key statistic with the key properties,
+/// returning whether any of them is significant, or `None` when there are
+/// too few keys for any of them to be
+fn correlations(subjects: &[&Subject], values: &[f64], threshold: f64) -> Option<bool> {
+    let mut significant = false;
+
+    let names = subjects[0].properties.values().map(|(name, _)| name);
+    for (i, name) in names.into_iter().enumerate() {
+        let property: Vec<f64> = subjects
+            .iter()
+            .map(|s| s.properties.values()[i].1)
+            .collect();
+        let (rho, p) = stats::spearman(&property, values);
+
+        let flag = if p < threshold { " *" } else { "" };
+        significant |= p < threshold;
+        println!("  {name:<14} {rho:>7.3} {p:>10.3e}{flag}");
+    }
+
+    let needed = keys_needed(threshold);
+    if subjects.len() < needed {
+        println!(
+            "  no p-value can go below {threshold:.3e} with {} keys, the test has no power (use at least {needed})",
+            subjects.len()
+        );
+        return None;
+    }
+
+    Some(significant)
+}
+
+/// Run the sweep and print the report
+pub fn sweep(args: &SweepArgs) -> Result<()> {
+    let started = environment::timestamp();
+
+    if args.alpha <= 0.0 || args.alpha >= 1.0 {
+        bail!("The significance level must be between 0 and 1");
+    }
+
+    interrupt::install()?;
+
+    let config = args.decrypt.config()?;
+
+    let mut subjects = Vec::new();
+    let mut backends = Vec::new();
+    let mut ciphertexts = Vec::new();
+    let mut owners = Vec::new();
+
+    for path in key_files(&args.keys)? {
+        let name = path.display().to_string();
+        let input = path.with_extension(&args.extension);
+
+        let key = PrivateKey::from_file(&name, KeyFormat::Auto, None)
+            .with_context(|| format!("Failed to load key {name}"))?;
+        let offset = ciphertexts.len();
+        for ciphertext in CiphertextSource::open(&input.display().to_string(), key.size())? {
+            ciphertexts.push(ciphertext?);
+            owners.push(subjects.len());
+        }
+        let count = ciphertexts.len() - offset;
+        if count == 0 {
+            bail!("No ciphertexts for key {name} in {}", input.display());
+        }
+
+        let labels = match &args.labels {
+            Some(extension) => {
+                let path = path.with_extension(extension);
+                let labels = measurements::read_labels(&path.display().to_string())?;
+                if labels.len() < count {
+                    bail!("Fewer labels than ciphertexts for key {name}");
+                }
+                labels
+            }
+            None => Vec::new(),
+        };
+
+        let properties = Properties::new(&key)?;
+        backends.push(key.decrypter(args.decrypt.backend, &config)?);
+
+        println!(
+            "key: {name} ({} bits, {count} ciphertexts)",
+            properties.bits
+        );
+
+        subjects.push(Subject {
+            name,
+            properties,
+            fingerprint: key.fingerprint()?,
+            offset,
+            ciphertexts: count,
+            labels,
+            samples: Vec::new(),
+        });
+    }
+
+    if subjects.len() < 3 {
+        bail!("At least three keys are needed to look for correlations");
+    }
+
+    let tuning = args.run.tuning().apply()?;
+    measure::print_tuning(&tuning);
+
+    let current = Rc::new(Cell::new(0));
+    let backend = Keyed {
+        backends,
+        current: current.clone(),
+    };
+    let implicit_rejection = backend.implicit_rejection();
+    let mut harness = Harness::new(Box::new(backend), Clock::new(args.decrypt.clock)?);
+    let unit = harness.unit();
+
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let counts: Vec<usize> = subjects.iter().map(|s| s.ciphertexts).collect();
+    let mut rng = ChaCha20Rng::seed_from_u64(seed);
+    let schedule = Schedule::interleaved(&counts, args.repeat as usize, &mut rng);
+
+    println!("schedule: {} decryptions", schedule.len());
+
+    let metadata = harness.metadata_mut();
+    metadata.push("keys", subjects.len());
+    metadata.push("repeat", args.repeat);
+    metadata.push("seed", seed);
+    metadata.append(&tuning);
+    if args.decrypt.padding == PaddingMode::Pkcs1 {
+        metadata.push(
+            "implicit-rejection",
+            measure::implicit_rejection_mode(implicit_rejection),
+        );
+    }
+
+    // Every ciphertext selects the backend of its key on its way to the
+    // harness
+    let select = |item: &Result<(usize, &[u8])>| {
+        if let Ok((index, _)) = item {
+            current.set(owners[*index]);
+        }
+    };
+
+    if let Some(warmup) = args.run.warmup {
+        let count = match warmup {
+            Warmup::Decryptions(count) => count,
+            Warmup::Input => ciphertexts.len(),
+        };
+        let source = std::iter::repeat_with(|| schedule.iter(&ciphertexts))
+            .flatten()
+            .take(count)
+            .inspect(select)
+            .map(|item| item.map(|(_, ciphertext)| ciphertext.to_vec()));
+        let count = harness.warm_up(source)?;
+        println!("warm-up: {count} decryptions");
+        harness.metadata_mut().push("warmup", count);
+    }
+
+    if args.run.discard_first > 0 {
+        harness.set_discard_first(args.run.discard_first);
+        harness
+            .metadata_mut()
+            .push("discard-first", args.run.discard_first);
+    }
+
+    let output =
+        BufWriter::new(File::create(&args.output).context("Failed to create output file")?);
+    let mut sink = SweepSink {
+        writer: output,
+        subjects: &mut subjects,
+        owners: &owners,
+    };
+    let summary = harness.run_indexed(
+        schedule.iter(&ciphertexts).inspect(select),
+        &mut [&mut sink],
+    )?;
+
+    println!(
+        "decryptions: {} ({} failed, {} discarded)",
+        summary.decryptions, summary.failures, summary.discarded
+    );
+
+    if summary.interrupted {
+        bail!("Interrupted after {} samples", summary.decryptions);
+    }
+
+    let header = if args.labels.is_some() {
+        "class spread"
+    } else {
+        "fail - ok"
+    };
+
+    println!();
+    println!("Per key medians ({unit}):");
+    println!(
+        "{:>5} {:>5} {:>4} {:>4} {:>5} {:>5} {:>12} {:>12}  key",
+        "bits", "bytes", "top0", "d0", "p", "q", "median", header
+    );
+
+    let mut diffs = Vec::new();
+    for subject in &subjects {
+        let median = subject.median(|_| true).unwrap_or_default();
+        let diff = subject.difference();
+
+        let p = &subject.properties;
+        println!(
+            "{:>5} {:>5} {:>4} {:>4} {:>5} {:>5} {:>12.0} {:>12}  {}",
+            p.bits,
+            p.bytes,
+            p.top_zero_bits,
+            p.d_zero_bytes,
+            p.p_bits,
+            p.q_bits,
+            median,
+            diff.map_or("-".to_owned(), |d| format!("{d:.0}")),
+            subject.name
+        );
+
+        if let Some(diff) = diff {
+            diffs.push((subject, diff));
+        }
+    }
+
+    let properties = subjects[0].properties.values().len();
+    let threshold = args.alpha / (2 * properties) as f64;
+
+    println!();
+    println!("Spearman correlations (alpha {threshold:.3e} after Bonferroni correction):");
+
+    println!("median duration:");
+    let all: Vec<&Subject> = subjects.iter().collect();
+    let medians: Vec<f64> = all
+        .iter()
+        .map(|s| s.median(|_| true).unwrap_or_default())
+        .collect();
+    correlations(&all, &medians, threshold);
+
+    // The median follows the key size anyway, only the difference between
+    // the classes points at a padding oracle
+    let mut leak = None;
+    if args.labels.is_some() {
+        println!("class spread:");
+    } else {
+        println!("fail - ok difference:");
+    }
+    if diffs.len() < 3 {
+        if args.labels.is_some() {
+            println!("  not enough keys with samples in two classes");
+        } else {
+            println!("  not enough keys with both failed and successful decryptions");
+            if implicit_rejection == Some(true) {
+                println!(
+                    "  implicit rejection is enabled, so no decryption fails: use --implicit-rejection off or --labels"
+                );
+            }
+        }
+    } else {
+        let (keys, values): (Vec<&Subject>, Vec<f64>) = diffs.into_iter().unzip();
+        leak = correlations(&keys, &values, threshold);
+    }
+
+    println!();
+    let (verdict, reason) = match leak {
+        Some(true) => ("FAIL", "timing differences correlate with key properties"),
+        Some(false) => ("PASS", "no key-dependent timing differences detected"),
+        None => ("INCONCLUSIVE", "not enough keys to compare"),
+    };
+    println!("verdict: {verdict}, {reason}");
+
+    let fields = json!({
+        "keys": subjects.iter().map(|s| json!({
+            "file": s.name,
+            "bits": s.properties.bits,
+            "sha256": s.fingerprint,
+            "ciphertexts": s.ciphertexts,
+        })).collect::<Vec<_>>(),
+        "summary": {
+            "decryptions": summary.decryptions,
+            "failures": summary.failures,
+            "discarded": summary.discarded,
+        },
+        "verdict": verdict,
+    });
+    let sidecar = measure::sidecar(
+        &started,
+        &measure::affinity(&tuning),
+        &args.decrypt,
+        harness.metadata(),
+        fields,
+    );
+
+    measure::write_sidecar(&format!("{}.json", args.output), &sidecar)
+}
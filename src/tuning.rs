@@ -0,0 +1,256 @@
+//! Scheduling setup of the measuring thread, to keep it from migrating
+//! between cores, being preempted or taking page faults
+
+use crate::harness::Metadata;
+use anyhow::{bail, Result};
+
+/// Scheduling settings requested for the measuring thread
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Tuning {
+    /// Core to pin the thread to
+    pub cpu: Option<usize>,
+    /// `SCHED_FIFO` priority, 1 to 99
+    pub fifo_priority: Option<i32>,
+    /// Lock all current and future pages in memory
+    pub mlock: bool,
+}
+
+impl Tuning {
+    /// Apply the settings to the calling thread, returning the effective
+    /// settings as metadata
+    ///
+    /// The metadata records whether the selected core is isolated from the
+    /// scheduler and the timer tick, see [`warnings`].
+    pub fn apply(&self) -> Result<Metadata> {
+        #[cfg(target_os = "linux")]
+        return linux::apply(self);
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            if self.cpu.is_some() || self.fifo_priority.is_some() || self.mlock {
+                bail!("CPU pinning, real-time scheduling and mlock are only supported on Linux");
+            }
+            Ok(Metadata::default())
+        }
+    }
+}
+
+/// Warnings about a pinned core left to the scheduler or the timer tick, from
+/// the metadata returned by [`Tuning::apply`]
+pub fn warnings(tuning: &Metadata) -> Vec<String> {
+    let cpu = tuning.get("affinity").unwrap_or("-");
+    let mut warnings = Vec::new();
+
+    if tuning.get("cpu-isolated") == Some("no") {
+        warnings.push(format!("CPU {cpu} is not isolated (isolcpus)"));
+    }
+    if tuning.get("cpu-nohz-full") == Some("no") {
+        warnings.push(format!("CPU {cpu} still gets the timer tick (nohz_full)"));
+    }
+
+    warnings
+}
+
+/// Parse a kernel CPU list such as `1-3,6`
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let (first, last): (usize, usize) = match range.split_once('-') {
+            Some((first, last)) => (first.parse()?, last.parse()?),
+            None => {
+                let cpu = range.parse()?;
+                (cpu, cpu)
+            }
+        };
+        if first > last {
+            bail!("Invalid CPU range {range}");
+        }
+        cpus.extend(first..=last);
+    }
+
+    Ok(cpus)
+}
+
+/// Format CPUs as a kernel CPU list, collapsing consecutive CPUs to ranges
+pub fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|&(first, last)| match first == last {
+            true => first.to_string(),
+            false => format!("{first}-{last}"),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{format_cpu_list, parse_cpu_list, Tuning};
+    use crate::harness::Metadata;
+    use anyhow::{bail, Context, Result};
+    use std::{io, mem};
+
+    /// Read a CPU list from sysfs, empty when the file does not exist
+    fn sysfs_cpus(name: &str) -> Vec<usize> {
+        std::fs::read_to_string(format!("/sys/devices/system/cpu/{name}"))
+            .ok()
+            .and_then(|list| parse_cpu_list(&list).ok())
+            .unwrap_or_default()
+    }
+
+    fn pin(cpu: usize) -> Result<()> {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            bail!("CPU {cpu} is out of range");
+        }
+
+        // SAFETY: the set is a plain bitmask, initialized by CPU_ZERO
+        let ret = unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            libc::CPU_ZERO(&mut set);
+            libc::CPU_SET(cpu, &mut set);
+            libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set)
+        };
+
+        if ret != 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("Failed to pin the measuring thread to CPU {cpu}"));
+        }
+
+        Ok(())
+    }
+
+    fn affinity() -> Result<Vec<usize>> {
+        // SAFETY: the set is a plain bitmask, filled in by the kernel
+        let (ret, set) = unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            let ret = libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set);
+            (ret, set)
+        };
+
+        if ret != 0 {
+            return Err(io::Error::last_os_error()).context("Failed to get the CPU affinity");
+        }
+
+        Ok((0..libc::CPU_SETSIZE as usize)
+            // SAFETY: the index is within the set
+            .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+            .collect())
+    }
+
+    fn set_fifo(priority: i32) -> Result<()> {
+        let param = libc::sched_param {
+            sched_priority: priority,
+        };
+
+        // SAFETY: the parameter is only read during the call
+        if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+            return Err(io::Error::last_os_error())
+                .context("Failed to set SCHED_FIFO scheduling (needs CAP_SYS_NICE)");
+        }
+
+        Ok(())
+    }
+
+    fn scheduler() -> String {
+        let mut param = libc::sched_param { sched_priority: 0 };
+
+        // SAFETY: plain syscalls on the calling thread
+        let policy = unsafe {
+            let policy = libc::sched_getscheduler(0);
+            libc::sched_getparam(0, &mut param);
+            policy
+        };
+
+        match policy {
+            libc::SCHED_FIFO => format!("fifo {}", param.sched_priority),
+            libc::SCHED_RR => format!("rr {}", param.sched_priority),
+            libc::SCHED_BATCH => "batch".to_owned(),
+            libc::SCHED_IDLE => "idle".to_owned(),
+            _ => "other".to_owned(),
+        }
+    }
+
+    fn mlockall() -> Result<()> {
+        // SAFETY: no memory is touched by the call
+        if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+            return Err(io::Error::last_os_error())
+                .context("Failed to lock memory (needs CAP_IPC_LOCK or a higher RLIMIT_MEMLOCK)");
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(tuning: &Tuning) -> Result<Metadata> {
+        let mut metadata = Metadata::default();
+
+        if let Some(cpu) = tuning.cpu {
+            pin(cpu)?;
+
+            let isolated = sysfs_cpus("isolated").contains(&cpu);
+            let nohz_full = sysfs_cpus("nohz_full").contains(&cpu);
+
+            metadata.push("cpu-isolated", if isolated { "yes" } else { "no" });
+            metadata.push("cpu-nohz-full", if nohz_full { "yes" } else { "no" });
+        }
+
+        if let Some(priority) = tuning.fifo_priority {
+            set_fifo(priority)?;
+        }
+
+        if tuning.mlock {
+            mlockall()?;
+        }
+
+        metadata.push("affinity", format_cpu_list(&affinity()?));
+        metadata.push("scheduler", scheduler());
+        metadata.push("mlock", if tuning.mlock { "yes" } else { "no" });
+
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cpu_lists() {
+        assert_eq!(parse_cpu_list("1-3,6\n").unwrap(), [1, 2, 3, 6]);
+        assert_eq!(parse_cpu_list("0").unwrap(), [0]);
+        assert_eq!(parse_cpu_list("4-4,0-1").unwrap(), [4, 0, 1]);
+        // Empty sysfs files, like nohz_full without the boot option
+        assert!(parse_cpu_list("\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_cpu_lists() {
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+        assert!(parse_cpu_list("1-").is_err());
+        assert!(parse_cpu_list("-1").is_err());
+    }
+
+    #[test]
+    fn formats_cpu_lists() {
+        assert_eq!(format_cpu_list(&[]), "");
+        assert_eq!(format_cpu_list(&[5]), "5");
+        assert_eq!(format_cpu_list(&[0, 1, 2, 4, 6, 7]), "0-2,4,6-7");
+        assert_eq!(format_cpu_list(&[3, 1, 2]), "3,1-2");
+    }
+
+    #[test]
+    fn cpu_lists_round_trip() {
+        let list = "0-3,8,10-11";
+        assert_eq!(format_cpu_list(&parse_cpu_list(list).unwrap()), list);
+    }
+}
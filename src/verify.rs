@@ -0,0 +1,388 @@
+use crate::{
+    harness::{Sample, Sink},
+    outcome::Outcome,
+};
+use anyhow::{Context, Result};
+use openssl::sha::sha256;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    str::FromStr,
+};
+
+/// Number of mismatches described before only counting them
+const MAX_REPORTED: usize = 10;
+
+/// Expected result of decrypting one ciphertext
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expected {
+    Plaintext(Vec<u8>),
+    /// The decryption must fail, or return a synthetic plaintext under
+    /// implicit rejection
+    Failure,
+}
+
+/// Read an expected plaintext file, one hex plaintext or `-` per line
+///
+/// Empty lines stand for empty plaintexts.
+pub fn read_expected(path: &str) -> Result<Vec<Expected>> {
+    let file = File::open(path).context("Failed to open expected plaintext file")?;
+
+    let mut expected = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.context("Failed to read expected plaintext file")?;
+        expected.push(match line.trim() {
+            "-" => Expected::Failure,
+            hex => Expected::Plaintext(hex::decode(hex).with_context(|| {
+                format!("Invalid hex in expected plaintext file on line {}", i + 1)
+            })?),
+        });
+    }
+
+    Ok(expected)
+}
+
+/// Result of one decryption, as compared across repeats
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Seen {
+    Plaintext([u8; 32]),
+    Failure(Outcome),
+}
+
+impl fmt::Display for Seen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Seen::Plaintext(hash) => write!(f, "sha256:{}", hex::encode(hash)),
+            Seen::Failure(outcome) => write!(f, "{outcome}"),
+        }
+    }
+}
+
+impl FromStr for Seen {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("sha256:") {
+            Some(hash) => {
+                let mut plaintext = [0; 32];
+                hex::decode_to_slice(hash, &mut plaintext).context("Invalid plaintext hash")?;
+                Ok(Seen::Plaintext(plaintext))
+            }
+            None => Ok(Seen::Failure(s.parse()?)),
+        }
+    }
+}
+
+/// Mismatch counts of a verified run
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Verification {
+    /// Samples compared with an expected result
+    pub checked: usize,
+    /// Wrong plaintext length or bytes
+    pub wrong_plaintext: usize,
+    /// Failed decryptions that should have succeeded
+    pub unexpected_failures: usize,
+    /// Successful decryptions that should have failed, without implicit
+    /// rejection
+    pub unexpected_successes: usize,
+    /// Ciphertexts whose result changed between repeats
+    pub nondeterministic: usize,
+}
+
+impl Verification {
+    pub fn mismatches(&self) -> usize {
+        self.wrong_plaintext
+            + self.unexpected_failures
+            + self.unexpected_successes
+            + self.nondeterministic
+    }
+}
+
+/// Check every decryption against the expected plaintexts, and every repeat
+/// of a ciphertext against its first decryption
+pub struct Verifier {
+    expected: Option<Vec<Expected>>,
+    implicit_rejection: bool,
+    /// First result of every ciphertext, the plaintext as a SHA-256 hash
+    seen: HashMap<usize, Seen>,
+    verification: Verification,
+    /// Descriptions of the first mismatches
+    reported: Vec<String>,
+}
+
+impl Verifier {
+    /// `implicit_rejection` accepts successful decryptions of ciphertexts
+    /// which are expected to fail
+    pub fn new(expected: Option<Vec<Expected>>, implicit_rejection: bool) -> Self {
+        Verifier {
+            expected,
+            implicit_rejection,
+            seen: HashMap::new(),
+            verification: Verification::default(),
+            reported: Vec::new(),
+        }
+    }
+
+    pub fn verification(&self) -> Verification {
+        self.verification
+    }
+
+    /// Descriptions of the first mismatches, the others are only counted
+    pub fn reported(&self) -> &[String] {
+        &self.reported
+    }
+
+    /// State of the verifier, to carry it over to a resumed run
+    pub fn state(&self) -> Value {
+        let v = &self.verification;
+        let seen: Map<String, Value> = self
+            .seen
+            .iter()
+            .map(|(index, seen)| (index.to_string(), Value::from(seen.to_string())))
+            .collect();
+
+        json!({
+            "checked": v.checked,
+            "wrong_plaintext": v.wrong_plaintext,
+            "unexpected_failures": v.unexpected_failures,
+            "unexpected_successes": v.unexpected_successes,
+            "nondeterministic": v.nondeterministic,
+            "seen": seen,
+        })
+    }
+
+    /// Continue from the [`Verifier::state`] of an interrupted run
+    pub fn restore(&mut self, state: &Value) -> Result<()> {
+        let count = |name: &str| -> Result<usize> {
+            state[name]
+                .as_u64()
+                .map(|n| n as usize)
+                .with_context(|| format!("No {name} in verifier state"))
+        };
+
+        self.verification = Verification {
+            checked: count("checked")?,
+            wrong_plaintext: count("wrong_plaintext")?,
+            unexpected_failures: count("unexpected_failures")?,
+            unexpected_successes: count("unexpected_successes")?,
+            nondeterministic: count("nondeterministic")?,
+        };
+
+        let seen = state["seen"]
+            .as_object()
+            .context("No seen results in verifier state")?;
+        for (index, seen) in seen {
+            let seen = seen.as_str().context("Invalid result in verifier state")?;
+            self.seen.insert(
+                index.parse().context("Invalid index in verifier state")?,
+                seen.parse()?,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn report(&mut self, message: std::fmt::Arguments) {
+        if self.verification.mismatches() <= MAX_REPORTED {
+            self.reported.push(message.to_string());
+        }
+    }
+}
+
+impl Sink for Verifier {
+    fn record(&mut self, sample: &Sample, plaintext: &[u8]) -> Result<()> {
+        let index = sample.index;
+
+        let seen = match sample.outcome {
+            Outcome::Ok => Seen::Plaintext(sha256(plaintext)),
+            outcome => Seen::Failure(outcome),
+        };
+        match self.seen.get(&index) {
+            Some(first) if *first != seen => {
+                self.verification.nondeterministic += 1;
+                self.report(format_args!(
+                    "ciphertext {index} decrypted differently than before"
+                ));
+            }
+            Some(_) => (),
+            None => {
+                self.seen.insert(index, seen);
+            }
+        }
+
+        let Some(expected) = self.expected.as_ref().and_then(|e| e.get(index)) else {
+            return Ok(());
+        };
+        self.verification.checked += 1;
+
+        match (expected, sample.outcome) {
+            (Expected::Plaintext(expected), Outcome::Ok) if expected.as_slice() != plaintext => {
+                self.verification.wrong_plaintext += 1;
+                self.report(format_args!(
+                    "ciphertext {index} decrypted to {} bytes, expected {}",
+                    plaintext.len(),
+                    expected.len()
+                ));
+            }
+            (Expected::Plaintext(_), Outcome::Ok) => (),
+            (Expected::Plaintext(_), outcome) => {
+                self.verification.unexpected_failures += 1;
+                self.report(format_args!("ciphertext {index} failed with {outcome}"));
+            }
+            (Expected::Failure, Outcome::Ok) if !self.implicit_rejection => {
+                self.verification.unexpected_successes += 1;
+                self.report(format_args!(
+                    "ciphertext {index} decrypted, expected a failure"
+                ));
+            }
+            (Expected::Failure, _) => (),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(index: usize, outcome: Outcome) -> Sample {
+        Sample {
+            index,
+            duration: 1,
+            outcome,
+            len: 0,
+            worker: 0,
+            cpu: None,
+        }
+    }
+
+    fn expected() -> Vec<Expected> {
+        vec![
+            Expected::Plaintext(vec![1, 2]),
+            Expected::Plaintext(vec![3]),
+            Expected::Failure,
+            Expected::Failure,
+        ]
+    }
+
+    #[test]
+    fn matching_results() {
+        let mut verifier = Verifier::new(Some(expected()), false);
+
+        verifier.record(&sample(0, Outcome::Ok), &[1, 2]).unwrap();
+        verifier.record(&sample(1, Outcome::Ok), &[3]).unwrap();
+        verifier.record(&sample(2, Outcome::Padding), &[]).unwrap();
+        verifier.record(&sample(3, Outcome::Error(4)), &[]).unwrap();
+        // Beyond the expected plaintexts
+        verifier.record(&sample(4, Outcome::Ok), &[5]).unwrap();
+
+        let v = verifier.verification();
+        assert_eq!(v.checked, 4);
+        assert_eq!(v.mismatches(), 0);
+        assert!(verifier.reported().is_empty());
+    }
+
+    #[test]
+    fn mismatches() {
+        let mut verifier = Verifier::new(Some(expected()), false);
+
+        verifier.record(&sample(0, Outcome::Ok), &[1, 3]).unwrap();
+        verifier.record(&sample(1, Outcome::Padding), &[]).unwrap();
+        verifier.record(&sample(2, Outcome::Ok), &[7; 16]).unwrap();
+
+        let v = verifier.verification();
+        assert_eq!(v.checked, 3);
+        assert_eq!(v.wrong_plaintext, 1);
+        assert_eq!(v.unexpected_failures, 1);
+        assert_eq!(v.unexpected_successes, 1);
+        assert_eq!(v.nondeterministic, 0);
+        assert_eq!(
+            verifier.reported(),
+            [
+                "ciphertext 0 decrypted to 2 bytes, expected 2",
+                "ciphertext 1 failed with padding",
+                "ciphertext 2 decrypted, expected a failure",
+            ]
+        );
+    }
+
+    #[test]
+    fn implicit_rejection_accepts_synthetic_plaintexts() {
+        let mut verifier = Verifier::new(Some(expected()), true);
+
+        verifier.record(&sample(2, Outcome::Ok), &[7; 16]).unwrap();
+        verifier.record(&sample(3, Outcome::Padding), &[]).unwrap();
+        // Still wrong for ciphertexts with an expected plaintext
+        verifier.record(&sample(0, Outcome::Ok), &[9]).unwrap();
+
+        let v = verifier.verification();
+        assert_eq!(v.unexpected_successes, 0);
+        assert_eq!(v.wrong_plaintext, 1);
+    }
+
+    #[test]
+    fn nondeterministic_repeats() {
+        let mut verifier = Verifier::new(None, true);
+
+        verifier.record(&sample(0, Outcome::Ok), &[1]).unwrap();
+        verifier.record(&sample(0, Outcome::Ok), &[1]).unwrap();
+        verifier.record(&sample(0, Outcome::Ok), &[2]).unwrap();
+        verifier.record(&sample(1, Outcome::Padding), &[]).unwrap();
+        verifier.record(&sample(1, Outcome::Error(3)), &[]).unwrap();
+
+        let v = verifier.verification();
+        assert_eq!(v.checked, 0);
+        assert_eq!(v.nondeterministic, 2);
+    }
+
+    #[test]
+    fn reports_only_the_first_mismatches() {
+        let mut verifier = Verifier::new(None, false);
+
+        for i in 0..MAX_REPORTED + 5 {
+            verifier.record(&sample(i, Outcome::Ok), &[1]).unwrap();
+            verifier.record(&sample(i, Outcome::Padding), &[]).unwrap();
+        }
+
+        assert_eq!(verifier.verification().nondeterministic, MAX_REPORTED + 5);
+        assert_eq!(verifier.reported().len(), MAX_REPORTED);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut verifier = Verifier::new(Some(expected()), false);
+        verifier.record(&sample(0, Outcome::Ok), &[1, 2]).unwrap();
+        verifier.record(&sample(1, Outcome::Padding), &[]).unwrap();
+        verifier.record(&sample(3, Outcome::Error(9)), &[]).unwrap();
+
+        let state = verifier.state();
+        let mut resumed = Verifier::new(Some(expected()), false);
+        resumed.restore(&state).unwrap();
+        assert_eq!(resumed.state(), state);
+
+        // Repeats after the resume are compared with the first run
+        resumed.record(&sample(0, Outcome::Ok), &[1, 2]).unwrap();
+        resumed.record(&sample(3, Outcome::Error(9)), &[]).unwrap();
+        resumed.record(&sample(1, Outcome::Ok), &[3]).unwrap();
+
+        let v = resumed.verification();
+        assert_eq!(v.checked, 6);
+        assert_eq!(v.unexpected_failures, 1);
+        assert_eq!(v.nondeterministic, 1);
+    }
+
+    #[test]
+    fn restore_rejects_invalid_states() {
+        let mut verifier = Verifier::new(None, false);
+
+        assert!(verifier.restore(&json!({})).is_err());
+
+        let mut state = Verifier::new(None, false).state();
+        state["seen"] = json!({ "0": "sha256:zz" });
+        assert!(verifier.restore(&state).is_err());
+    }
+}